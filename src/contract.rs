@@ -2,31 +2,71 @@
 use cosmwasm_std::entry_point;
 
 use cosmwasm_std::{
-    coin, ensure_eq, to_binary, wasm_execute, Addr, BankMsg, Binary, CosmosMsg, Deps, DepsMut, Env,
-    Event, MessageInfo, Order, Response, StdResult, Uint128,
+    coin, ensure_eq, to_binary, wasm_execute, Addr, BankMsg, Binary, Coin, CosmosMsg, Deps,
+    DepsMut, DistributionMsg, Empty, Env, Event, HexBinary, MessageInfo, Order, OverflowError,
+    OverflowOperation, Reply, Response, StakingMsg, StdError, StdResult, SubMsgResult, Timestamp,
+    Uint128, WasmMsg,
 };
+use cw_storage_plus::{Bound, Map};
+use cw_utils::{Duration, Expiration};
+use std::cmp::Reverse;
 
 use nois::{ints_in_range, NoisCallback, ProxyExecuteMsg};
-use std::ops::{Add, Mul};
 
 use cw2::set_contract_version;
 
 use crate::error::ContractError;
 use crate::helpers::{
-    build_expiration_time, calculate_prize_distribution, calculate_tickets_prize,
-    calculate_winner_per_match, check_tickets, create_next_draw,
-    ensure_is_enough_funds_to_cover_tickets, ensure_ticket_is_valid,
+    accrue_deposit_weight, add_rewards_to_pot, add_sent_randomness_fees, apply_config_changes,
+    build_expiration_time, calculate_fixed_prizes_needed, calculate_matches, calculate_payouts,
+    calculate_solvency, calculate_tickets_prize, calculate_winner_per_match, change_gaming_limits,
+    check_tickets, checked_add_u64, create_next_draw, distribute_overflow, draw_tickets,
+    draw_tickets_page, ensure_address_is_allowed, ensure_allowlist_is_valid, ensure_bps_is_valid,
+    ensure_exact_funds_for_tickets, ensure_funds_in_denom, ensure_keeper_bounty_is_valid,
+    ensure_loyalty_is_valid, ensure_no_loss_denom_is_bonded, ensure_prize_tiers_are_valid,
+    ensure_remote_sender_is_valid, ensure_sales_shares_are_valid, ensure_schedule_is_valid,
+    ensure_second_chance_is_valid, ensure_tickets_can_be_bought, ensure_tickets_can_move,
+    ensure_voucher_is_valid, gaming_limits_at, is_sold_out, next_draw_end_time, next_purchase_at,
+    prize_payout_msg, query_nft_owner, query_pending_rewards, rebuild_liabilities, recent_spend,
+    record_config_change, record_tickets, second_chance_rank, shared_percentages,
+    take_keeper_bounty, take_randomness_fee, take_tickets, takes_weighted_pick, ticket_holder_addr,
+    update_liabilities, update_prize_per_match, BPS_DENOMINATOR, IBC_PAYOUT_REPLY_ID,
 };
-use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, UpdateConfigMsg};
+use crate::ibc::transfer_sequence;
+use crate::msg::{
+    Cw721ExecuteMsg, DepositResponse, ExecuteMsg, GamingLimitsResponse, IbcLifecycleComplete,
+    InstantiateMsg, MigrateMsg, PriceQuote, QueryMsg, ReferralStatsResponse, SolvencyResponse,
+    SudoMsg, TicketMetadata, UpdateConfigMsg, Voucher,
+};
+use crate::pricing::{ensure_pricing_is_valid, quote_tickets};
 use crate::state::{
-    Config, Draw, Status, TicketResult, CONFIG, DRAWS, DRAWS_INDEX, REQUESTS, TICKETS, WINNERS,
+    Config, ConfigHistoryEntry, Deposit, Draw, Liabilities, LoyaltyPoints, OverflowPolicy,
+    PendingConfig, Purchase, ReferralStats, RemoteAccount, SecondChancePick, SecondChanceRaffle,
+    SecondChanceStatus, SecondChanceWinner, Sponsorship, Status, TicketResult, Unbonding,
+    UnderSubscribedPolicy, ALLOWLIST, CONFIG, CONFIG_HISTORY, DELEGATIONS, DENYLIST, DEPOSITS,
+    DRAWS, DRAWS_INDEX, FAILED_PAYOUTS, GAMING_LIMITS, IBC_PAYOUTS, JACKPOT, LIABILITIES,
+    LOYALTY_POINTS, LOYALTY_RESERVE, NFT_CLAIMS, NFT_TICKETS, NFT_TOKEN_INDEX, NO_LOSS_RAFFLES,
+    PENDING_CONFIG, PROMO_BUDGET, PURCHASES, RANDOMNESS_FEES, REFERRAL_CREDITS, REFERRAL_STATS,
+    REFERRERS, REFUNDS, REMOTE_ACCOUNTS, REQUESTS, RESERVE, SECOND_CHANCE_RAFFLES,
+    SECOND_CHANCE_REQUESTS, SECOND_CHANCE_WINNERS, SENDING_IBC_PAYOUTS, SPEND_LEDGER, SPONSORS,
+    TICKETS, UNBONDING, UNBONDING_RELEASES, USED_VOUCHERS, WINNERS,
 };
 
 const CONTRACT_NAME: &str = "crates.io:super-star";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const SECOND_CHANCE_JOB_PREFIX: &str = "second-chance/";
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(_deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    if LIABILITIES.may_load(deps.storage)?.is_none() {
+        let liabilities = rebuild_liabilities(deps.storage)?;
+        LIABILITIES.save(deps.storage, &liabilities)?;
+    }
+
     let event = Event::new("superstar.v1.MsgMigrateContract");
     Ok(Response::new().add_event(event))
 }
@@ -42,19 +82,94 @@ pub fn instantiate(
 
     let nois_proxy_addr = deps.api.addr_validate(&msg.nois_proxy)?;
 
+    // Exactly one of them sets the tiers, the percentages then mirror the prize tiers
+    let percentage_per_match = match (msg.percentage_per_match, msg.prize_tiers) {
+        (Some(percentage_per_match), None) => percentage_per_match,
+        (None, Some(prize_tiers)) => shared_percentages(&prize_tiers),
+        _ => return Err(ContractError::InvalidPrizeTiers),
+    };
+
     let config = Config {
-        owner: deps.api.addr_canonicalize(&info.sender.as_str())?,
+        owner: deps.api.addr_canonicalize(info.sender.as_str())?,
         interval: msg.draw_interval,
         ticket_price: msg.ticket_price,
         treasury_fee: msg.treasury_fee,
         nois_proxy: nois_proxy_addr,
         request_timeout: msg.request_timeout,
-        percentage_per_match: msg.percentage_per_match,
+        percentage_per_match,
         max_tickets_per_user: msg.max_tickets_per_user,
+        min_jackpot: msg.min_jackpot,
+        jackpot_cap: msg.jackpot_cap,
+        prize_tiers: msg.prize_tiers,
+        pricing: msg.pricing,
+        referral_bps: msg.referral_bps,
+        voucher_pubkey: msg.voucher_pubkey,
+        loyalty: msg.loyalty,
+        second_chance: msg.second_chance,
+        progressive_jackpot_bps: msg.progressive_jackpot_bps,
+        ticket_nft: msg
+            .ticket_nft
+            .map(|addr| deps.api.addr_validate(&addr))
+            .transpose()?,
+        no_loss: msg.no_loss,
+        config_timelock: msg.config_timelock,
+        paused: false,
+        allowlist: ensure_allowlist_is_valid(deps.api, msg.allowlist.unwrap_or_default())?,
+        max_tickets_per_draw: msg.max_tickets_per_draw,
+        min_participation: msg.min_participation,
+        schedule_anchor: msg.schedule_anchor,
+        keeper_bounty: msg.keeper_bounty,
+        randomness_fee_bps: msg.randomness_fee_bps,
+        auto_raffle_max_tickets: msg.auto_raffle_max_tickets,
     };
 
+    if let Some(pricing) = &config.pricing {
+        ensure_pricing_is_valid(pricing)?;
+    }
+
+    if let Some(referral_bps) = config.referral_bps {
+        ensure_bps_is_valid(referral_bps)?;
+    }
+
+    if let Some(loyalty) = &config.loyalty {
+        ensure_loyalty_is_valid(loyalty)?;
+    }
+
+    if let Some(second_chance) = &config.second_chance {
+        ensure_second_chance_is_valid(second_chance)?;
+    }
+
+    if let Some(progressive_jackpot_bps) = config.progressive_jackpot_bps {
+        ensure_bps_is_valid(progressive_jackpot_bps)?;
+    }
+
+    if let Some(schedule_anchor) = &config.schedule_anchor {
+        ensure_schedule_is_valid(schedule_anchor, &config.interval)?;
+    }
+
+    if let Some(keeper_bounty) = &config.keeper_bounty {
+        ensure_keeper_bounty_is_valid(keeper_bounty)?;
+    }
+
+    if let Some(randomness_fee_bps) = config.randomness_fee_bps {
+        ensure_bps_is_valid(randomness_fee_bps)?;
+    }
+
+    ensure_prize_tiers_are_valid(&config.tiers())?;
+    ensure_sales_shares_are_valid(&config)?;
+    ensure_no_loss_denom_is_bonded(&deps.querier, &config)?;
+
+    // Deposit weights are measured in seconds, so draws must be time based
+    if let Some(no_loss) = &config.no_loss {
+        if no_loss.validators.is_empty() || !matches!(config.interval, Duration::Time(_)) {
+            return Err(ContractError::InvalidGameMode);
+        }
+    }
+
     CONFIG.save(deps.storage, &config)?;
     DRAWS_INDEX.save(deps.storage, &0)?;
+    RESERVE.save(deps.storage, &Uint128::zero())?;
+    LIABILITIES.save(deps.storage, &Liabilities::default())?;
 
     create_next_draw(deps, &env, Uint128::zero())?;
 
@@ -71,14 +186,96 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::BuyTickets { tickets, draw_id } => {
-            buy_tickets(deps, env, info, tickets, draw_id)
+        ExecuteMsg::BuyTickets {
+            tickets,
+            draw_id,
+            referrer,
+            voucher,
+        } => buy_tickets(deps, env, info, tickets, draw_id, referrer, voucher),
+        ExecuteMsg::Raffle { draw_id, limit } => raffle(deps, env, info, draw_id, limit),
+        ExecuteMsg::ClaimPrize { draw_id, token_ids } => {
+            claim_prize(deps, env, info, draw_id, token_ids)
         }
-        ExecuteMsg::Raffle { draw_id } => raffle(deps, env, draw_id),
-        ExecuteMsg::ClaimPrize { draw_id } => claim_prize(deps, info, draw_id),
         ExecuteMsg::RequestRandomness { draw_id } => request_randomness(deps, env, info, draw_id),
-        ExecuteMsg::NoisReceive { callback } => receive_randomness(deps, info, callback),
-        ExecuteMsg::UpdateConfig { new_config } => update_config(deps, info, new_config),
+        ExecuteMsg::NoisReceive { callback } => receive_randomness(deps, env, info, callback),
+        ExecuteMsg::UpdateConfig { new_config } => update_config(deps, env, info, *new_config),
+        ExecuteMsg::FundPot { draw_id } => fund_pot(deps, env, info, draw_id),
+        ExecuteMsg::FundReserve {} => fund_reserve(deps, info),
+        ExecuteMsg::RecoverSurplus { amount, recipient } => {
+            recover_surplus(deps, env, info, amount, recipient)
+        }
+        ExecuteMsg::Deposit {} => deposit(deps, env, info),
+        ExecuteMsg::Withdraw { amount } => withdraw(deps, env, info, amount),
+        ExecuteMsg::ClaimUnbonded {} => claim_unbonded(deps, env, info),
+        ExecuteMsg::ProposeConfigChange { new_config } => {
+            propose_config_change(deps, env, info, *new_config)
+        }
+        ExecuteMsg::ApplyConfigChange {} => apply_config_change(deps, env, info),
+        ExecuteMsg::CancelConfigChange {} => cancel_config_change(deps, info),
+        ExecuteMsg::ClaimReferralRewards {} => claim_referral_rewards(deps, env, info),
+        ExecuteMsg::FundPromoBudget {} => fund_promo_budget(deps, info),
+        ExecuteMsg::FundLoyaltyReserve {} => fund_loyalty_reserve(deps, info),
+        ExecuteMsg::RedeemPoints { tickets, draw_id } => {
+            redeem_points(deps, env, info, tickets, draw_id)
+        }
+        ExecuteMsg::RequestSecondChance { draw_id } => {
+            request_second_chance(deps, env, info, draw_id)
+        }
+        ExecuteMsg::RaffleSecondChance { draw_id, limit } => {
+            raffle_second_chance(deps, env, draw_id, limit)
+        }
+        ExecuteMsg::TransferTickets {
+            draw_id,
+            tickets,
+            recipient,
+        } => transfer_tickets(deps, env, info, draw_id, tickets, recipient),
+        ExecuteMsg::MintTickets { draw_id, tickets } => {
+            mint_tickets(deps, env, info, draw_id, tickets)
+        }
+        ExecuteMsg::IbcBuyTickets {
+            tickets,
+            draw_id,
+            channel,
+            remote_sender,
+        } => ibc_buy_tickets(deps, env, info, tickets, draw_id, channel, remote_sender),
+        ExecuteMsg::UpdateAllowlist { add, remove } => {
+            update_address_list(deps, info, ALLOWLIST, "allowlist", add, remove)
+        }
+        ExecuteMsg::UpdateDenylist { add, remove } => {
+            update_address_list(deps, info, DENYLIST, "denylist", add, remove)
+        }
+        ExecuteMsg::SetGamingLimits {
+            daily_limit,
+            weekly_limit,
+            cooldown,
+        } => set_gaming_limits(deps, env, info, daily_limit, weekly_limit, cooldown),
+        ExecuteMsg::SelfExclude { until } => self_exclude(deps, env, info, until),
+        ExecuteMsg::ClaimRefund { draw_id } => claim_refund(deps, env, info, draw_id),
+        ExecuteMsg::FundRandomnessFees {} => fund_randomness_fees(deps, info),
+        ExecuteMsg::RetryPayouts {} => retry_payouts(deps, env, info),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        IBC_PAYOUT_REPLY_ID => track_ibc_payout(deps, msg.result),
+        _ => Err(ContractError::InvalidReply),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcAck {
+            channel,
+            sequence,
+            success,
+            ..
+        }) => settle_ibc_payout(deps, channel, sequence, success),
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcTimeout { channel, sequence }) => {
+            settle_ibc_payout(deps, channel, sequence, false)
+        }
     }
 }
 
@@ -88,265 +285,2217 @@ pub fn buy_tickets(
     info: MessageInfo,
     tickets: Vec<String>,
     draw_id: u64,
+    referrer: Option<String>,
+    voucher: Option<Voucher>,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-    let mut draw = DRAWS.load(deps.storage, draw_id)?;
 
-    if draw.status != Status::Open || draw.end_time.is_expired(&env.block) {
-        return Err(ContractError::DrawIsNotOpen);
-    }
+    let (mut draw, n_tickets) = ensure_tickets_can_be_bought(
+        deps.as_ref(),
+        &env,
+        &config,
+        &info.sender,
+        draw_id,
+        &tickets,
+    )?;
 
-    let mut tickets_bought = TICKETS
-        .may_load(deps.storage, (draw_id, info.sender.clone()))?
-        .unwrap_or_default();
+    let quote = quote_tickets(
+        &config.ticket_price,
+        config.pricing.as_ref(),
+        n_tickets,
+        env.block.time,
+    )?;
+    let price = quote.price;
 
-    let n_tickets = tickets.len() as u32;
+    // A voucher covers part of the price out of the promo budget, the pot still gets it all
+    let subsidy = match &voucher {
+        Some(voucher) => {
+            ensure_voucher_is_valid(deps.as_ref(), &env, &config, &info.sender, voucher)?;
 
-    if n_tickets.add(tickets_bought.len() as u32) > config.max_tickets_per_user {
-        return Err(ContractError::MaxTicketsPerUserExceeded);
-    }
+            price
+                .amount
+                .checked_multiply_ratio(voucher.discount_bps, BPS_DENOMINATOR)?
+                .min(voucher.max_subsidy)
+        }
+        None => Uint128::zero(),
+    };
 
-    tickets.iter().try_for_each(ensure_ticket_is_valid)?;
+    let required_funds = coin(price.amount.checked_sub(subsidy)?.u128(), &price.denom);
 
-    let required_funds = coin(
-        config.ticket_price.amount.u128().mul(u128::from(n_tickets)),
-        config.ticket_price.denom,
-    );
+    ensure_exact_funds_for_tickets(&required_funds, &info.funds)?;
+
+    if let Some(voucher) = &voucher {
+        let promo_budget = PROMO_BUDGET.may_load(deps.storage)?.unwrap_or_default();
+        let promo_budget = promo_budget
+            .checked_sub(subsidy)
+            .map_err(|_| ContractError::PromoBudgetExhausted)?;
+        PROMO_BUDGET.save(deps.storage, &promo_budget)?;
+        USED_VOUCHERS.save(deps.storage, &voucher.nonce, &info.sender)?;
+    }
 
-    ensure_is_enough_funds_to_cover_tickets(&required_funds, &info.funds)?;
+    // The first referrer a buyer comes with is kept for good
+    let referrer = match REFERRERS.may_load(deps.storage, info.sender.clone())? {
+        Some(referrer) => Some(referrer),
+        None => match referrer {
+            Some(referrer) => {
+                let referrer = deps.api.addr_validate(&referrer)?;
+                if referrer == info.sender {
+                    return Err(ContractError::InvalidReferrer);
+                }
+
+                REFERRERS.save(deps.storage, info.sender.clone(), &referrer)?;
+                REFERRAL_STATS.update(
+                    deps.storage,
+                    referrer.clone(),
+                    |stats| -> Result<ReferralStats, ContractError> {
+                        let mut stats = stats.unwrap_or_default();
+                        stats.referred_users = checked_add_u64(stats.referred_users, 1)?;
+                        Ok(stats)
+                    },
+                )?;
+                Some(referrer)
+            }
+            None => None,
+        },
+    };
 
-    tickets_bought.extend(tickets);
+    // Referrers earn on what the buyer paid, not on what promotions covered
+    if let Some(referrer) = &referrer {
+        let credit = required_funds
+            .amount
+            .checked_multiply_ratio(config.referral_bps.unwrap_or_default(), BPS_DENOMINATOR)?;
 
-    TICKETS.save(
-        deps.storage,
-        (draw_id, info.sender.clone()),
-        &tickets_bought,
-    )?;
+        REFERRAL_STATS.update(
+            deps.storage,
+            referrer.clone(),
+            |stats| -> Result<ReferralStats, ContractError> {
+                let mut stats = stats.unwrap_or_default();
+                stats.tickets_sold = checked_add_u64(stats.tickets_sold, u64::from(n_tickets))?;
+                stats.total_earned = stats.total_earned.checked_add(credit)?;
+                Ok(stats)
+            },
+        )?;
+
+        if !credit.is_zero() {
+            REFERRAL_CREDITS.update(
+                deps.storage,
+                (referrer.clone(), draw_id),
+                |credits| -> StdResult<Uint128> {
+                    Ok(credits.unwrap_or_default().checked_add(credit)?)
+                },
+            )?;
+            draw.referral_rewards = draw.referral_rewards.checked_add(credit)?;
+        }
+    }
 
-    draw.total_tickets = draw.total_tickets.add(u64::from(n_tickets));
-    draw.total_prize.amount += required_funds.amount;
+    let points = match &config.loyalty {
+        Some(loyalty) => loyalty
+            .points_per_ticket
+            .checked_mul(u64::from(n_tickets))
+            .ok_or_else(|| {
+                OverflowError::new(OverflowOperation::Mul, loyalty.points_per_ticket, n_tickets)
+            })?,
+        None => 0,
+    };
 
-    draw.prize_per_match = Some(calculate_prize_distribution(
-        draw.total_prize.amount.clone(),
-        config.percentage_per_match.clone(),
-    ));
+    if points > 0 {
+        LOYALTY_POINTS.update(
+            deps.storage,
+            info.sender.clone(),
+            |loyalty| -> Result<LoyaltyPoints, ContractError> {
+                let mut loyalty = loyalty.unwrap_or_default();
+                loyalty.balance = checked_add_u64(loyalty.balance, points)?;
+                loyalty.lifetime = checked_add_u64(loyalty.lifetime, points)?;
+                Ok(loyalty)
+            },
+        )?;
+    }
 
-    DRAWS.save(deps.storage, draw_id, &draw)?;
+    let tickets_bought = record_tickets(
+        deps.storage,
+        &env,
+        &config,
+        &info.sender,
+        &mut draw,
+        tickets,
+        Purchase {
+            tickets: n_tickets,
+            free_tickets: quote.free_tickets,
+            paid: required_funds.clone(),
+            subsidy,
+            points: 0,
+        },
+    )?;
 
     let event = Event::new("superstar.v1.MsgBuyTickets")
         .add_attribute("draw_id", draw_id.to_string())
         .add_attribute("buyer", info.sender)
-        .add_attribute("tickets_bought", format!("{:?}", tickets_bought));
+        .add_attribute("tickets_bought", format!("{:?}", tickets_bought))
+        .add_attribute("paid", required_funds.to_string())
+        .add_attribute("voucher_subsidy", subsidy.to_string())
+        .add_attribute("loyalty_points", points.to_string())
+        .add_attribute(
+            "referrer",
+            referrer.map(|r| r.to_string()).unwrap_or_default(),
+        );
 
     Ok(Response::new().add_event(event))
 }
 
-pub fn claim_prize(
+pub fn fund_pot(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     draw_id: u64,
 ) -> Result<Response, ContractError> {
-    let draw = DRAWS.load(deps.storage, draw_id)?;
+    let config = CONFIG.load(deps.storage)?;
 
-    if draw.status != Status::Claimable {
-        return Err(ContractError::DrawIsNotClaimable);
+    if config.paused {
+        return Err(ContractError::ContractPaused);
     }
 
-    let tickets = TICKETS.load(deps.storage, (draw_id, info.sender.clone()))?;
-
-    let t_result = check_tickets(
-        tickets,
-        draw.winner_number.ok_or(ContractError::InvalidRandomness)?,
-    );
-
-    let prize = calculate_tickets_prize(
-        t_result,
-        draw.prize_per_match.unwrap_or_default(),
-        draw.winners_per_match.unwrap_or_default(),
-        draw.ticket_price.clone().denom,
-    );
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
 
-    if prize.amount.is_zero() {
-        return Err(ContractError::NoPrizeToClaim);
+    if draw.status != Status::Open || draw.end_time.is_expired(&env.block) {
+        return Err(ContractError::DrawIsNotOpen);
     }
 
-    if WINNERS
-        .may_load(deps.storage, (draw_id, info.sender.clone()))?
-        .is_some()
-    {
-        return Err(ContractError::PrizeAlreadyClaimed);
-    }
+    let amount = ensure_funds_in_denom(&draw.total_prize.denom, &info.funds)?;
 
-    WINNERS.save(
+    SPONSORS.update(
         deps.storage,
         (draw_id, info.sender.clone()),
-        &draw.ticket_price,
+        |sponsored| -> StdResult<Uint128> {
+            Ok(sponsored.unwrap_or_default().checked_add(amount)?)
+        },
     )?;
 
-    let event = Event::new("superstar.v1.MsgClaimPrize")
+    draw.total_prize.amount = draw.total_prize.amount.checked_add(amount)?;
+
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.open_pots = liabilities.open_pots.checked_add(amount)?;
+        Ok(liabilities)
+    })?;
+
+    update_prize_per_match(deps.storage, &config, &mut draw)?;
+
+    DRAWS.save(deps.storage, draw_id, &draw)?;
+
+    let event = Event::new("superstar.v1.MsgFundPot")
         .add_attribute("draw_id", draw_id.to_string())
-        .add_attribute("winner", info.sender.to_string())
-        .add_attribute("prize", prize.to_string());
+        .add_attribute("sponsor", info.sender)
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("total_prize", draw.total_prize.to_string());
 
-    Ok(Response::new()
-        .add_message(CosmosMsg::Bank(BankMsg::Send {
-            to_address: info.sender.to_string(),
-            amount: vec![prize.clone()],
-        }))
-        .add_event(event))
+    Ok(Response::new().add_event(event))
 }
 
-pub fn request_randomness(
-    deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-    id: u64,
-) -> Result<Response, ContractError> {
-    let mut draw = DRAWS.load(deps.storage, id)?;
+pub fn deposit(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let no_loss = config.no_loss.ok_or(ContractError::InvalidGameMode)?;
 
-    if !draw.end_time.is_expired(&env.block) {
-        return Err(ContractError::DrawIsOpen);
+    if config.paused {
+        return Err(ContractError::ContractPaused);
     }
 
-    if let Some(request) = REQUESTS.may_load(deps.storage, id)? {
-        if !request.is_expired(&env.block) {
-            return Err(ContractError::RandomnessAlreadyRequested);
-        }
-    }
+    let amount = ensure_funds_in_denom(&config.ticket_price.denom, &info.funds)?;
 
-    if draw.status.ne(&Status::Pending) {
-        draw.status = Status::Pending;
-        DRAWS.save(deps.storage, id, &draw)?;
-    }
+    let draw_id = DRAWS_INDEX.load(deps.storage)?;
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
 
-    let config = CONFIG.load(deps.storage)?;
+    let deposit = match DEPOSITS.may_load(deps.storage, info.sender.clone())? {
+        Some(deposit) => accrue_deposit_weight(&deposit, &draw, env.block.time)?,
+        None => Deposit {
+            principal: Uint128::zero(),
+            weight: Uint128::zero(),
+            last_update: env.block.time,
+            draw_id,
+        },
+    };
 
-    REQUESTS.save(
-        deps.storage,
-        id,
-        &build_expiration_time(&env, config.request_timeout)?,
-    )?;
+    let deposit = Deposit {
+        principal: deposit.principal.checked_add(amount)?,
+        ..deposit
+    };
 
-    let msg = wasm_execute(
-        config.nois_proxy.to_string(),
-        &ProxyExecuteMsg::GetNextRandomness {
-            job_id: id.to_string(),
-        },
-        info.funds,
+    // Spread the stake by always delegating to the validator with the least of it
+    let mut validator = None;
+    for v in no_loss.validators {
+        let delegated = DELEGATIONS.may_load(deps.storage, &v)?.unwrap_or_default();
+        if validator.as_ref().map_or(true, |(_, min)| delegated < *min) {
+            validator = Some((v, delegated));
+        }
+    }
+    let (validator, delegated) = validator.ok_or(ContractError::InvalidGameMode)?;
+
+    let rewards = query_pending_rewards(
+        &deps.querier,
+        &env.contract.address,
+        &validator,
+        &config.ticket_price.denom,
     )?;
+    add_rewards_to_pot(deps.storage, &mut draw, rewards)?;
 
-    let event = Event::new("superstar.v1.MsgCloseDraw")
-        .add_attribute("draw_id", id.to_string())
-        .add_attribute("closed_at", env.block.time.seconds().to_string());
+    DELEGATIONS.save(deps.storage, &validator, &delegated.checked_add(amount)?)?;
+    DEPOSITS.save(deps.storage, info.sender.clone(), &deposit)?;
+    DRAWS.save(deps.storage, draw_id, &draw)?;
 
-    Ok(Response::new().add_event(event).add_message(msg))
+    let event = Event::new("superstar.v1.MsgDeposit")
+        .add_attribute("depositor", info.sender)
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("principal", deposit.principal.to_string())
+        .add_attribute("validator", validator.clone());
+
+    Ok(Response::new()
+        .add_message(StakingMsg::Delegate {
+            validator,
+            amount: coin(amount.u128(), config.ticket_price.denom),
+        })
+        .add_event(event))
 }
 
-pub fn receive_randomness(
+pub fn withdraw(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
-    callback: NoisCallback,
+    amount: Uint128,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-    ensure_eq!(info.sender, config.nois_proxy, ContractError::Unauthorized);
+    let no_loss = config.no_loss.ok_or(ContractError::InvalidGameMode)?;
 
-    let draw_id = callback.job_id.parse::<u64>()?;
+    let deposit = DEPOSITS
+        .may_load(deps.storage, info.sender.clone())?
+        .ok_or(ContractError::InsufficientFunds)?;
 
-    let mut draw = DRAWS.load(deps.storage, draw_id)?;
+    if amount.is_zero() || amount > deposit.principal {
+        return Err(ContractError::InsufficientFunds);
+    }
 
-    ensure_eq!(
-        draw.status,
-        Status::Pending,
-        ContractError::DrawIsNotPending
-    );
+    let draw_id = DRAWS_INDEX.load(deps.storage)?;
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
 
-    let randomness: [u8; 32] = callback
-        .randomness
-        .to_array()
-        .map_err(|_| ContractError::InvalidRandomness)?;
+    // Weight accumulated so far is kept, the withdrawn principal just stops accruing
+    let deposit = accrue_deposit_weight(&deposit, &draw, env.block.time)?;
+    let deposit = Deposit {
+        principal: deposit.principal.checked_sub(amount)?,
+        ..deposit
+    };
 
-    let result = ints_in_range(randomness, 6, 0, 9);
+    // Undelegate from the validators holding the most stake first
+    let mut delegations = DELEGATIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(String, Uint128)>>>()?;
+    delegations.sort_by_key(|(_, delegated)| Reverse(*delegated));
 
-    let winner_number = result
-        .into_iter()
-        .fold(String::new(), |acc, x| acc + &x.to_string());
+    let mut messages = vec![];
+    let mut remaining = amount;
 
-    draw.winner_number = Some(winner_number.clone());
-    draw.status = Status::Raffling;
+    for (validator, delegated) in delegations {
+        if remaining.is_zero() {
+            break;
+        }
 
-    DRAWS.save(deps.storage, draw_id, &draw)?;
-    REQUESTS.remove(deps.storage, draw_id);
+        let undelegated = remaining.min(delegated);
+        remaining = remaining.checked_sub(undelegated)?;
 
-    let event = Event::new("superstar.v1.MsgReceiveRandomness")
-        .add_attribute("draw_id", draw_id.to_string())
-        .add_attribute(
-            "winner_number",
-            draw.winner_number.unwrap_or_default().to_string(),
-        );
+        let rewards = query_pending_rewards(
+            &deps.querier,
+            &env.contract.address,
+            &validator,
+            &config.ticket_price.denom,
+        )?;
+        add_rewards_to_pot(deps.storage, &mut draw, rewards)?;
 
-    Ok(Response::new().add_event(event))
-}
+        DELEGATIONS.save(
+            deps.storage,
+            &validator,
+            &delegated.checked_sub(undelegated)?,
+        )?;
 
-pub fn raffle(deps: DepsMut, env: Env, draw_id: u64) -> Result<Response, ContractError> {
-    let mut draw = DRAWS.load(deps.storage, draw_id)?;
+        messages.push(StakingMsg::Undelegate {
+            validator,
+            amount: coin(undelegated.u128(), &config.ticket_price.denom),
+        });
+    }
 
-    ensure_eq!(
-        draw.status,
-        Status::Raffling,
-        ContractError::DrawIsNotClaimable
-    );
+    if !remaining.is_zero() {
+        return Err(ContractError::InsufficientFunds);
+    }
 
-    let purchases = TICKETS
-        .prefix(draw_id)
-        .range(deps.storage, None, None, Order::Ascending)
-        .collect::<StdResult<Vec<(Addr, Vec<String>)>>>()?;
+    let release_at = env.block.time.plus_seconds(no_loss.unbonding_period);
 
-    let winner_number = draw.winner_number.clone().unwrap_or_default();
+    let mut unbonding = UNBONDING
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+    unbonding.push(Unbonding { amount, release_at });
+    UNBONDING.save(deps.storage, info.sender.clone(), &unbonding)?;
 
-    let winners_per_match = calculate_winner_per_match(purchases, winner_number.clone());
+    // Released principal is back in the balance, it no longer needs to be counted apart
+    let released = UNBONDING_RELEASES
+        .keys(
+            deps.storage,
+            None,
+            Some(Bound::inclusive(env.block.time.seconds())),
+            Order::Ascending,
+        )
+        .collect::<StdResult<Vec<u64>>>()?;
+    for release in released {
+        UNBONDING_RELEASES.remove(deps.storage, release);
+    }
+    UNBONDING_RELEASES.update(
+        deps.storage,
+        release_at.seconds(),
+        |releasing| -> StdResult<Uint128> {
+            Ok(releasing.unwrap_or_default().checked_add(amount)?)
+        },
+    )?;
 
-    draw.status = Status::Claimable;
-    draw.winners_per_match = Some(winners_per_match.clone());
+    if deposit.principal.is_zero() && deposit.weight.is_zero() {
+        DEPOSITS.remove(deps.storage, info.sender.clone());
+    } else {
+        DEPOSITS.save(deps.storage, info.sender.clone(), &deposit)?;
+    }
 
     DRAWS.save(deps.storage, draw_id, &draw)?;
 
-    let accumulative_pot = draw
-        .prize_per_match
-        .unwrap_or_default()
-        .iter()
-        .enumerate()
-        .fold(Uint128::zero(), |acc, (i, x)| {
-            if winners_per_match[i].eq(&0) {
-                acc.add(&x.clone())
-            } else {
-                acc
-            }
-        });
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.pending_withdrawals = liabilities.pending_withdrawals.checked_add(amount)?;
+        Ok(liabilities)
+    })?;
 
+    let event = Event::new("superstar.v1.MsgWithdraw")
+        .add_attribute("depositor", info.sender)
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("principal", deposit.principal.to_string())
+        .add_attribute("release_at", release_at.seconds().to_string());
+
+    Ok(Response::new().add_messages(messages).add_event(event))
+}
+
+pub fn claim_unbonded(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
 
-    let treasury_fee = accumulative_pot.multiply_ratio(config.treasury_fee, Uint128::from(100u128));
+    let (released, unbonding): (Vec<Unbonding>, Vec<Unbonding>) = UNBONDING
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default()
+        .into_iter()
+        .partition(|u| u.release_at <= env.block.time);
 
-    let mut response: Response = Response::new();
+    let amount = released
+        .iter()
+        .try_fold(Uint128::zero(), |acc, u| acc.checked_add(u.amount))?;
 
-    if !treasury_fee.is_zero() {
-        response = response.add_message(CosmosMsg::Bank(BankMsg::Send {
-            to_address: deps.api.addr_humanize(&config.owner)?.to_string(),
-            amount: vec![coin(treasury_fee.u128(), config.ticket_price.denom)],
-        }));
+    if amount.is_zero() {
+        return Err(ContractError::NothingUnbonded);
     }
 
-    create_next_draw(deps, &env, accumulative_pot)?;
+    if unbonding.is_empty() {
+        UNBONDING.remove(deps.storage, info.sender.clone());
+    } else {
+        UNBONDING.save(deps.storage, info.sender.clone(), &unbonding)?;
+    }
 
-    let event = Event::new("superstar.v1.MsgRaffle")
-        .add_attribute("draw_id", draw_id.to_string())
-        .add_attribute("winner_number", winner_number.to_string());
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.pending_withdrawals = liabilities.pending_withdrawals.checked_sub(amount)?;
+        Ok(liabilities)
+    })?;
 
-    Ok(response.add_event(event))
-}
+    let event = Event::new("superstar.v1.MsgClaimUnbonded")
+        .add_attribute("depositor", info.sender.to_string())
+        .add_attribute("amount", amount.to_string());
 
-pub fn update_config(
-    deps: DepsMut,
+    let payout = coin(amount.u128(), config.ticket_price.denom);
+
+    Ok(Response::new()
+        .add_submessage(prize_payout_msg(deps.storage, &env, &info.sender, payout)?)
+        .add_event(event))
+}
+
+pub fn claim_referral_rewards(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let credits = REFERRAL_CREDITS
+        .prefix(info.sender.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(u64, Uint128)>>>()?;
+
+    let mut amount = Uint128::zero();
+    let mut unfunded = Uint128::zero();
+
+    // Only raffled draws have had their credits funded
+    for (draw_id, credit) in credits {
+        let draw = DRAWS.load(deps.storage, draw_id)?;
+
+        if draw.status != Status::Claimable {
+            continue;
+        }
+
+        let funded = credit.checked_multiply_ratio(draw.referral_funded, draw.referral_rewards)?;
+        amount = amount.checked_add(funded)?;
+        unfunded = unfunded.checked_add(credit.checked_sub(funded)?)?;
+        REFERRAL_CREDITS.remove(deps.storage, (info.sender.clone(), draw_id));
+    }
+
+    if amount.is_zero() {
+        return Err(ContractError::NoReferralRewards);
+    }
+
+    REFERRAL_STATS.update(
+        deps.storage,
+        info.sender.clone(),
+        |stats| -> StdResult<ReferralStats> {
+            let mut stats = stats.unwrap_or_default();
+            stats.total_earned = stats.total_earned.checked_sub(unfunded)?;
+            stats.total_claimed = stats.total_claimed.checked_add(amount)?;
+            Ok(stats)
+        },
+    )?;
+
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.accrued_fees = liabilities.accrued_fees.checked_sub(amount)?;
+        Ok(liabilities)
+    })?;
+
+    let event = Event::new("superstar.v1.MsgClaimReferralRewards")
+        .add_attribute("referrer", info.sender.to_string())
+        .add_attribute("amount", amount.to_string());
+
+    let payout = coin(amount.u128(), config.ticket_price.denom);
+
+    Ok(Response::new()
+        .add_submessage(prize_payout_msg(deps.storage, &env, &info.sender, payout)?)
+        .add_event(event))
+}
+
+pub fn fund_reserve(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != deps.api.addr_humanize(&config.owner)? {
+        return Err(ContractError::Unauthorized);
+    }
+
+    let amount = ensure_funds_in_denom(&config.ticket_price.denom, &info.funds)?;
+
+    let reserve = RESERVE
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        .checked_add(amount)?;
+    RESERVE.save(deps.storage, &reserve)?;
+
+    // Refresh the open draw so its jackpot reflects the new guarantee coverage
+    let draw_id = DRAWS_INDEX.load(deps.storage)?;
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
+
+    if draw.status == Status::Open {
+        update_prize_per_match(deps.storage, &config, &mut draw)?;
+        DRAWS.save(deps.storage, draw_id, &draw)?;
+    }
+
+    let event = Event::new("superstar.v1.MsgFundReserve")
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("reserve", reserve.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+/// Trades loyalty points for tickets, paid for by the loyalty reserve
+pub fn redeem_points(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    tickets: Vec<String>,
+    draw_id: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let loyalty = config
+        .loyalty
+        .as_ref()
+        .ok_or(ContractError::InvalidLoyaltyConfig)?;
+
+    // Redeemed tickets are paid with points only
+    ensure_exact_funds_for_tickets(&coin(0, &config.ticket_price.denom), &info.funds)?;
+
+    let (mut draw, n_tickets) = ensure_tickets_can_be_bought(
+        deps.as_ref(),
+        &env,
+        &config,
+        &info.sender,
+        draw_id,
+        &tickets,
+    )?;
+
+    let points = loyalty
+        .points_per_free_ticket
+        .checked_mul(u64::from(n_tickets))
+        .ok_or_else(|| {
+            OverflowError::new(
+                OverflowOperation::Mul,
+                loyalty.points_per_free_ticket,
+                n_tickets,
+            )
+        })?;
+
+    let mut balance = LOYALTY_POINTS
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+
+    balance.balance = balance
+        .balance
+        .checked_sub(points)
+        .ok_or(ContractError::InsufficientPoints)?;
+    balance.redeemed = checked_add_u64(balance.redeemed, points)?;
+
+    let price = config
+        .ticket_price
+        .amount
+        .checked_mul(Uint128::from(n_tickets))?;
+
+    let loyalty_reserve = LOYALTY_RESERVE
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        .checked_sub(price)
+        .map_err(|_| ContractError::LoyaltyReserveExhausted)?;
+
+    LOYALTY_RESERVE.save(deps.storage, &loyalty_reserve)?;
+    LOYALTY_POINTS.save(deps.storage, info.sender.clone(), &balance)?;
+
+    let tickets_bought = record_tickets(
+        deps.storage,
+        &env,
+        &config,
+        &info.sender,
+        &mut draw,
+        tickets,
+        Purchase {
+            tickets: n_tickets,
+            free_tickets: n_tickets,
+            paid: coin(0, &config.ticket_price.denom),
+            subsidy: price,
+            points,
+        },
+    )?;
+
+    let event = Event::new("superstar.v1.MsgRedeemPoints")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("buyer", info.sender)
+        .add_attribute("tickets_bought", format!("{:?}", tickets_bought))
+        .add_attribute("points", points.to_string())
+        .add_attribute("balance", balance.balance.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+pub fn fund_loyalty_reserve(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != deps.api.addr_humanize(&config.owner)? {
+        return Err(ContractError::Unauthorized);
+    }
+
+    let amount = ensure_funds_in_denom(&config.ticket_price.denom, &info.funds)?;
+
+    let loyalty_reserve = LOYALTY_RESERVE
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        .checked_add(amount)?;
+    LOYALTY_RESERVE.save(deps.storage, &loyalty_reserve)?;
+
+    let event = Event::new("superstar.v1.MsgFundLoyaltyReserve")
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("loyalty_reserve", loyalty_reserve.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+pub fn fund_promo_budget(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != deps.api.addr_humanize(&config.owner)? {
+        return Err(ContractError::Unauthorized);
+    }
+
+    let amount = ensure_funds_in_denom(&config.ticket_price.denom, &info.funds)?;
+
+    let promo_budget = PROMO_BUDGET
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        .checked_add(amount)?;
+    PROMO_BUDGET.save(deps.storage, &promo_budget)?;
+
+    let event = Event::new("superstar.v1.MsgFundPromoBudget")
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("promo_budget", promo_budget.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+/// Tightens the sender's limits right away, looser limits only apply after a delay
+pub fn set_gaming_limits(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    daily_limit: Option<Uint128>,
+    weekly_limit: Option<Uint128>,
+    cooldown: Option<u64>,
+) -> Result<Response, ContractError> {
+    let limits = GAMING_LIMITS
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+
+    let limits = change_gaming_limits(limits, env.block.time, daily_limit, weekly_limit, cooldown);
+
+    GAMING_LIMITS.save(deps.storage, info.sender.clone(), &limits)?;
+
+    let event = Event::new("superstar.v1.MsgSetGamingLimits")
+        .add_attribute("addr", info.sender)
+        .add_attribute("daily_limit", format!("{:?}", daily_limit))
+        .add_attribute("weekly_limit", format!("{:?}", weekly_limit))
+        .add_attribute("cooldown", format!("{:?}", cooldown))
+        .add_attribute(
+            "activates_at",
+            format!("{:?}", limits.pending.map(|pending| pending.activates_at)),
+        );
+
+    Ok(Response::new().add_event(event))
+}
+
+/// Blocks the sender's purchases until `until`, an exclusion can only be extended
+pub fn self_exclude(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    until: Timestamp,
+) -> Result<Response, ContractError> {
+    let mut limits = GAMING_LIMITS
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+
+    if until <= env.block.time || limits.excluded_until.is_some_and(|current| until < current) {
+        return Err(ContractError::InvalidExclusion);
+    }
+
+    limits.excluded_until = Some(until);
+    GAMING_LIMITS.save(deps.storage, info.sender.clone(), &limits)?;
+
+    let event = Event::new("superstar.v1.MsgSelfExclude")
+        .add_attribute("addr", info.sender)
+        .add_attribute("until", until.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+/// Adds and removes addresses of the allowlist or the denylist
+pub fn update_address_list(
+    deps: DepsMut,
+    info: MessageInfo,
+    list: Map<Addr, Empty>,
+    name: &str,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != deps.api.addr_humanize(&config.owner)? {
+        return Err(ContractError::Unauthorized);
+    }
+
+    for addr in &add {
+        list.save(deps.storage, deps.api.addr_validate(addr)?, &Empty {})?;
+    }
+
+    for addr in &remove {
+        list.remove(deps.storage, deps.api.addr_validate(addr)?);
+    }
+
+    let event = Event::new("superstar.v1.MsgUpdateAddressList")
+        .add_attribute("list", name)
+        .add_attribute("added", format!("{:?}", add))
+        .add_attribute("removed", format!("{:?}", remove));
+
+    Ok(Response::new().add_event(event))
+}
+
+/// Tops up the prepaid randomness fees, in any denom the Nois proxy accepts
+pub fn fund_randomness_fees(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    if info.funds.is_empty() {
+        return Err(ContractError::InvalidCoin);
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    add_sent_randomness_fees(deps.storage, &deps.querier, &config.nois_proxy, &info.funds)?;
+
+    let event = Event::new("superstar.v1.MsgFundRandomnessFees")
+        .add_attribute("funder", info.sender.to_string())
+        .add_attribute("amount", format!("{:?}", info.funds));
+
+    Ok(Response::new().add_event(event))
+}
+
+pub fn claim_prize(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    draw_id: u64,
+    token_ids: Option<Vec<String>>,
+) -> Result<Response, ContractError> {
+    let draw = DRAWS.load(deps.storage, draw_id)?;
+
+    if draw.status != Status::Claimable {
+        return Err(ContractError::DrawIsNotClaimable);
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    ensure_address_is_allowed(deps.as_ref(), &config, &info.sender)?;
+
+    if let Some(token_ids) = token_ids {
+        return claim_nft_prize(deps, env, info, draw, token_ids);
+    }
+
+    let tickets = TICKETS.load(deps.storage, (draw_id, info.sender.clone()))?;
+
+    let t_result = check_tickets(
+        tickets,
+        draw.winner_number.ok_or(ContractError::InvalidRandomness)?,
+    );
+
+    let prize = calculate_tickets_prize(
+        t_result,
+        draw.prize_per_match.unwrap_or_default(),
+        draw.winners_per_match.unwrap_or_default(),
+        draw.ticket_price.clone().denom,
+    )?;
+
+    if prize.amount.is_zero() {
+        return Err(ContractError::NoPrizeToClaim);
+    }
+
+    if WINNERS
+        .may_load(deps.storage, (draw_id, info.sender.clone()))?
+        .is_some()
+    {
+        return Err(ContractError::PrizeAlreadyClaimed);
+    }
+
+    WINNERS.save(
+        deps.storage,
+        (draw_id, info.sender.clone()),
+        &draw.ticket_price,
+    )?;
+
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.unclaimed_prizes = liabilities.unclaimed_prizes.checked_sub(prize.amount)?;
+        Ok(liabilities)
+    })?;
+
+    let event = Event::new("superstar.v1.MsgClaimPrize")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("winner", info.sender.to_string())
+        .add_attribute("prize", prize.to_string());
+
+    Ok(Response::new()
+        .add_submessage(prize_payout_msg(deps.storage, &env, &info.sender, prize)?)
+        .add_event(event))
+}
+
+/// Buys tickets for an account on another chain, recording them under the intermediate
+/// address ibc-hooks derives for it. That address stands for the remote account alone, so
+/// every payout made to it is sent back over the same channel
+pub fn ibc_buy_tickets(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    tickets: Vec<String>,
+    draw_id: u64,
+    channel: String,
+    remote_sender: String,
+) -> Result<Response, ContractError> {
+    ensure_remote_sender_is_valid(&info.sender, &channel, &remote_sender)?;
+
+    REMOTE_ACCOUNTS.save(
+        deps.storage,
+        info.sender.clone(),
+        &RemoteAccount {
+            channel: channel.clone(),
+            sender: remote_sender.clone(),
+        },
+    )?;
+
+    let sender = info.sender.clone();
+    let response = buy_tickets(deps, env, info, tickets, draw_id, None, None)?;
+
+    let event = Event::new("superstar.v1.MsgIbcBuyTickets")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("buyer", sender)
+        .add_attribute("channel", channel)
+        .add_attribute("remote_sender", remote_sender);
+
+    Ok(response.add_event(event))
+}
+
+/// Moves tickets to another address while the draw is open, or once it is claimable
+/// as long as neither side has claimed its prize yet and no second chance is underway
+pub fn transfer_tickets(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    draw_id: u64,
+    tickets: Vec<String>,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    if recipient == info.sender {
+        return Err(ContractError::InvalidRecipient);
+    }
+
+    ensure_address_is_allowed(deps.as_ref(), &config, &info.sender)?;
+    ensure_address_is_allowed(deps.as_ref(), &config, &recipient)?;
+
+    let draw = DRAWS.load(deps.storage, draw_id)?;
+    ensure_tickets_can_move(deps.storage, &env, &draw, &[&info.sender, &recipient])?;
+
+    let mut sender_tickets = TICKETS
+        .may_load(deps.storage, (draw_id, info.sender.clone()))?
+        .unwrap_or_default();
+
+    take_tickets(&mut sender_tickets, &tickets)?;
+
+    let mut recipient_tickets = TICKETS
+        .may_load(deps.storage, (draw_id, recipient.clone()))?
+        .unwrap_or_default();
+
+    if tickets.len().checked_add(recipient_tickets.len())
+        > Some(config.max_tickets_per_user as usize)
+    {
+        return Err(ContractError::MaxTicketsPerUserExceeded);
+    }
+
+    recipient_tickets.extend(tickets.iter().cloned());
+
+    if sender_tickets.is_empty() {
+        TICKETS.remove(deps.storage, (draw_id, info.sender.clone()));
+    } else {
+        TICKETS.save(
+            deps.storage,
+            (draw_id, info.sender.clone()),
+            &sender_tickets,
+        )?;
+    }
+    TICKETS.save(
+        deps.storage,
+        (draw_id, recipient.clone()),
+        &recipient_tickets,
+    )?;
+
+    let event = Event::new("superstar.v1.MsgTransferTickets")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("from", info.sender.to_string())
+        .add_attribute("to", recipient.to_string())
+        .add_attribute("tickets", format!("{:?}", tickets));
+
+    Ok(Response::new().add_event(event))
+}
+
+/// Mints tickets as a single CW721 token, after which they belong to the token owner
+pub fn mint_tickets(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    draw_id: u64,
+    tickets: Vec<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_address_is_allowed(deps.as_ref(), &config, &info.sender)?;
+    let ticket_nft = config.ticket_nft.ok_or(ContractError::NoTicketNft)?;
+
+    if tickets.is_empty() {
+        return Err(ContractError::InvalidTicket);
+    }
+
+    let draw = DRAWS.load(deps.storage, draw_id)?;
+    ensure_tickets_can_move(deps.storage, &env, &draw, &[&info.sender])?;
+
+    let mut owned = TICKETS
+        .may_load(deps.storage, (draw_id, info.sender.clone()))?
+        .unwrap_or_default();
+
+    take_tickets(&mut owned, &tickets)?;
+
+    if owned.is_empty() {
+        TICKETS.remove(deps.storage, (draw_id, info.sender.clone()));
+    } else {
+        TICKETS.save(deps.storage, (draw_id, info.sender.clone()), &owned)?;
+    }
+
+    let token_index = checked_add_u64(
+        NFT_TOKEN_INDEX.may_load(deps.storage)?.unwrap_or_default(),
+        1,
+    )?;
+    NFT_TOKEN_INDEX.save(deps.storage, &token_index)?;
+    let token_id = token_index.to_string();
+    NFT_TICKETS.save(deps.storage, (draw_id, &token_id), &tickets)?;
+
+    let mint = WasmMsg::Execute {
+        contract_addr: ticket_nft.to_string(),
+        msg: to_binary(&Cw721ExecuteMsg::Mint {
+            token_id: token_id.clone(),
+            owner: info.sender.to_string(),
+            token_uri: None,
+            extension: TicketMetadata {
+                draw_id,
+                numbers: tickets.clone(),
+            },
+        })?,
+        funds: vec![],
+    };
+
+    let event = Event::new("superstar.v1.MsgMintTickets")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("owner", info.sender.to_string())
+        .add_attribute("token_id", token_id)
+        .add_attribute("tickets", format!("{:?}", tickets));
+
+    Ok(Response::new().add_message(mint).add_event(event))
+}
+
+/// Pays the tickets of NFTs owned by the sender, each token can only be claimed once
+fn claim_nft_prize(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    draw: Draw,
+    token_ids: Vec<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let ticket_nft = config.ticket_nft.ok_or(ContractError::NoTicketNft)?;
+
+    let mut tickets = vec![];
+
+    for token_id in &token_ids {
+        let numbers = NFT_TICKETS
+            .may_load(deps.storage, (draw.id, token_id))?
+            .ok_or(ContractError::TicketNotOwned)?;
+
+        if query_nft_owner(&deps.querier, &ticket_nft, token_id)? != info.sender.as_str() {
+            return Err(ContractError::TicketNotOwned);
+        }
+
+        if NFT_CLAIMS.has(deps.storage, (draw.id, token_id)) {
+            return Err(ContractError::PrizeAlreadyClaimed);
+        }
+
+        NFT_CLAIMS.save(deps.storage, (draw.id, token_id), &info.sender)?;
+        tickets.extend(numbers);
+    }
+
+    let t_result = check_tickets(
+        tickets,
+        draw.winner_number.ok_or(ContractError::InvalidRandomness)?,
+    );
+
+    let prize = calculate_tickets_prize(
+        t_result,
+        draw.prize_per_match.unwrap_or_default(),
+        draw.winners_per_match.unwrap_or_default(),
+        draw.ticket_price.denom,
+    )?;
+
+    if prize.amount.is_zero() {
+        return Err(ContractError::NoPrizeToClaim);
+    }
+
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.unclaimed_prizes = liabilities.unclaimed_prizes.checked_sub(prize.amount)?;
+        Ok(liabilities)
+    })?;
+
+    let event = Event::new("superstar.v1.MsgClaimPrize")
+        .add_attribute("draw_id", draw.id.to_string())
+        .add_attribute("winner", info.sender.to_string())
+        .add_attribute("token_ids", format!("{:?}", token_ids))
+        .add_attribute("prize", prize.to_string());
+
+    Ok(Response::new()
+        .add_submessage(prize_payout_msg(deps.storage, &env, &info.sender, prize)?)
+        .add_event(event))
+}
+
+pub fn request_randomness(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let mut draw = DRAWS.load(deps.storage, id)?;
+    let config = CONFIG.load(deps.storage)?;
+
+    // Only an open draw, or a pending one whose request timed out, can ask Nois again
+    if !matches!(draw.status, Status::Open | Status::Pending) {
+        return Err(ContractError::DrawIsNotOpen);
+    }
+
+    // A sold out draw can be closed right away
+    if !draw.end_time.is_expired(&env.block) && !is_sold_out(&config, &draw) {
+        return Err(ContractError::DrawIsOpen);
+    }
+
+    if let Some(request) = REQUESTS.may_load(deps.storage, id)? {
+        if !request.is_expired(&env.block) {
+            return Err(ContractError::RandomnessAlreadyRequested);
+        }
+    }
+
+    if let Some(min_participation) = &config.min_participation {
+        if config.no_loss.is_none()
+            && draw.status == Status::Open
+            && draw.total_tickets < min_participation.tickets
+        {
+            let policy = min_participation.policy.clone();
+            return settle_under_subscribed_draw(deps, env, info, &config, draw, policy);
+        }
+    }
+
+    // Whatever the caller sends tops up the fees, the request itself is paid by the contract
+    add_sent_randomness_fees(deps.storage, &deps.querier, &config.nois_proxy, &info.funds)?;
+    let fee = take_randomness_fee(deps.storage, &deps.querier, &config.nois_proxy)?;
+
+    let mut response = Response::new();
+
+    // In no-loss mode the pot is whatever the stake earned while the draw was open
+    if config.no_loss.is_some() {
+        let delegations = DELEGATIONS
+            .range(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<(String, Uint128)>>>()?;
+
+        for (validator, delegated) in delegations {
+            if delegated.is_zero() {
+                continue;
+            }
+
+            let rewards = query_pending_rewards(
+                &deps.querier,
+                &env.contract.address,
+                &validator,
+                &config.ticket_price.denom,
+            )?;
+            add_rewards_to_pot(deps.storage, &mut draw, rewards)?;
+
+            response = response.add_message(DistributionMsg::WithdrawDelegatorReward { validator });
+        }
+    }
+
+    let already_paid = draw.request_bounty.is_some();
+    let keeper_bounty = take_keeper_bounty(deps.storage, &config, &mut draw, already_paid)?;
+
+    if let Some(bounty) = &keeper_bounty {
+        draw.request_bounty = Some(bounty.clone());
+        if !bounty.amount.is_zero() {
+            response = response.add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: vec![bounty.clone()],
+            });
+        }
+    }
+
+    draw.status = Status::Pending;
+    DRAWS.save(deps.storage, id, &draw)?;
+
+    REQUESTS.save(
+        deps.storage,
+        id,
+        &build_expiration_time(&env, config.request_timeout)?,
+    )?;
+
+    let msg = wasm_execute(
+        config.nois_proxy.to_string(),
+        &ProxyExecuteMsg::GetNextRandomness {
+            job_id: id.to_string(),
+        },
+        fee,
+    )?;
+
+    let event = Event::new("superstar.v1.MsgCloseDraw")
+        .add_attribute("draw_id", id.to_string())
+        .add_attribute("closed_at", env.block.time.seconds().to_string())
+        .add_attribute(
+            "keeper_bounty",
+            keeper_bounty.map(|b| b.to_string()).unwrap_or_default(),
+        );
+
+    Ok(response.add_event(event).add_message(msg))
+}
+
+/// Extends, refunds or rolls over a draw that did not sell enough tickets to be raffled
+fn settle_under_subscribed_draw(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    config: &Config,
+    mut draw: Draw,
+    policy: UnderSubscribedPolicy,
+) -> Result<Response, ContractError> {
+    let mut response = Response::new();
+
+    // Whatever was sent for the randomness fee is not needed
+    if !info.funds.is_empty() {
+        response = response.add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: info.funds,
+        });
+    }
+
+    if policy == UnderSubscribedPolicy::Extend {
+        draw.end_time = next_draw_end_time(config, &env)?;
+        DRAWS.save(deps.storage, draw.id, &draw)?;
+
+        let event = Event::new("superstar.v1.MsgExtendDraw")
+            .add_attribute("draw_id", draw.id.to_string())
+            .add_attribute("total_tickets", draw.total_tickets.to_string())
+            .add_attribute("end_time", draw.end_time.to_string());
+
+        return Ok(response.add_event(event));
+    }
+
+    // The tickets of an under-subscribed draw are few enough to go through them here
+    let purchases = PURCHASES
+        .prefix(draw.id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(Addr, Vec<Purchase>)>>>()?;
+
+    let next_draw_id = checked_add_u64(draw.id, 1)?;
+    let mut refunds = Uint128::zero();
+
+    // Credits follow the tickets into the next draw, or are gone with a refund
+    let mut referrers = purchases
+        .iter()
+        .filter_map(|(buyer, _)| REFERRERS.may_load(deps.storage, buyer.clone()).transpose())
+        .collect::<StdResult<Vec<Addr>>>()?;
+    referrers.sort();
+    referrers.dedup();
+
+    for referrer in referrers {
+        let Some(credit) = REFERRAL_CREDITS.may_load(deps.storage, (referrer.clone(), draw.id))?
+        else {
+            continue;
+        };
+        REFERRAL_CREDITS.remove(deps.storage, (referrer.clone(), draw.id));
+
+        if policy == UnderSubscribedPolicy::Rollover {
+            REFERRAL_CREDITS.save(deps.storage, (referrer, next_draw_id), &credit)?;
+        } else {
+            REFERRAL_STATS.update(
+                deps.storage,
+                referrer,
+                |stats| -> StdResult<ReferralStats> {
+                    let mut stats = stats.unwrap_or_default();
+                    stats.total_earned = stats.total_earned.checked_sub(credit)?;
+                    Ok(stats)
+                },
+            )?;
+        }
+    }
+
+    if policy == UnderSubscribedPolicy::Refund {
+        let mut paid = Uint128::zero();
+        let mut promo_refund = Uint128::zero();
+        let mut loyalty_refund = Uint128::zero();
+
+        for (buyer, purchases) in &purchases {
+            for purchase in purchases {
+                paid = paid.checked_add(purchase.paid.amount)?;
+
+                if purchase.points > 0 {
+                    loyalty_refund = loyalty_refund.checked_add(purchase.subsidy)?;
+                    LOYALTY_POINTS.update(
+                        deps.storage,
+                        buyer.clone(),
+                        |points| -> Result<LoyaltyPoints, ContractError> {
+                            let mut points = points.unwrap_or_default();
+                            points.balance = checked_add_u64(points.balance, purchase.points)?;
+                            points.redeemed = points.redeemed.saturating_sub(purchase.points);
+                            Ok(points)
+                        },
+                    )?;
+                } else {
+                    promo_refund = promo_refund.checked_add(purchase.subsidy)?;
+                }
+            }
+        }
+
+        // The jackpot and the randomness fees give back what is left of this draw's
+        // contributions, whatever was already won or paid to Nois is gone
+        let jackpot = JACKPOT.may_load(deps.storage)?.unwrap_or_default();
+        let jackpot_recovered = jackpot.min(draw.jackpot_contribution);
+        JACKPOT.save(deps.storage, &jackpot.checked_sub(jackpot_recovered)?)?;
+
+        let denom = draw.total_prize.denom.as_str();
+        let randomness_fees = RANDOMNESS_FEES
+            .may_load(deps.storage, denom)?
+            .unwrap_or_default();
+        let randomness_fees_recovered = randomness_fees.min(draw.randomness_fee_contribution);
+        RANDOMNESS_FEES.save(
+            deps.storage,
+            denom,
+            &randomness_fees.checked_sub(randomness_fees_recovered)?,
+        )?;
+
+        let pot = draw
+            .total_prize
+            .amount
+            .checked_add(draw.second_chance_pool)?
+            .checked_add(jackpot_recovered)?
+            .checked_add(randomness_fees_recovered)?;
+
+        // Only what the pot still holds can be refunded
+        let paid = paid.min(pot.saturating_sub(promo_refund.checked_add(loyalty_refund)?));
+
+        // Refunds follow the tickets, whoever holds them now gets back an equal share
+        // per ticket of what was paid. Tokens without a known owner leave their share
+        // in the pot
+        let holders = draw_tickets(deps.storage, draw.id)?;
+        let n_tickets = holders
+            .iter()
+            .map(|(_, tickets)| tickets.len() as u128)
+            .sum::<u128>();
+
+        for (holder, tickets) in holders {
+            let Some(addr) = ticket_holder_addr(deps.as_ref(), config, holder) else {
+                continue;
+            };
+
+            let refund = paid.multiply_ratio(tickets.len() as u128, n_tickets);
+            if refund.is_zero() {
+                continue;
+            }
+
+            REFUNDS.update(
+                deps.storage,
+                (draw.id, addr),
+                |current| -> StdResult<Uint128> {
+                    Ok(current.unwrap_or_default().checked_add(refund)?)
+                },
+            )?;
+            refunds = refunds.checked_add(refund)?;
+        }
+
+        if !promo_refund.is_zero() {
+            let promo_budget = PROMO_BUDGET.may_load(deps.storage)?.unwrap_or_default();
+            PROMO_BUDGET.save(deps.storage, &promo_budget.checked_add(promo_refund)?)?;
+        }
+
+        if !loyalty_refund.is_zero() {
+            let loyalty_reserve = LOYALTY_RESERVE.may_load(deps.storage)?.unwrap_or_default();
+            LOYALTY_RESERVE.save(deps.storage, &loyalty_reserve.checked_add(loyalty_refund)?)?;
+        }
+
+        let owed = refunds
+            .checked_add(promo_refund)?
+            .checked_add(loyalty_refund)?;
+
+        // Sponsorships and the previous rollover stay in play
+        let next_prize = pot.saturating_sub(owed);
+
+        update_liabilities(deps.storage, |mut liabilities| {
+            liabilities.open_pots = liabilities
+                .open_pots
+                .checked_sub(draw.total_prize.amount)?
+                .checked_sub(draw.second_chance_pool)?
+                .checked_add(next_prize)?;
+            liabilities.refunds = liabilities.refunds.checked_add(refunds)?;
+            Ok(liabilities)
+        })?;
+
+        create_next_draw(deps.branch(), &env, next_prize)?;
+    } else {
+        create_next_draw(deps.branch(), &env, draw.total_prize.amount)?;
+
+        let tickets = TICKETS
+            .prefix(draw.id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<(Addr, Vec<String>)>>>()?;
+
+        for (holder, tickets) in tickets {
+            TICKETS.remove(deps.storage, (draw.id, holder.clone()));
+            TICKETS.save(deps.storage, (next_draw_id, holder), &tickets)?;
+        }
+
+        for (buyer, purchases) in purchases {
+            PURCHASES.remove(deps.storage, (draw.id, buyer.clone()));
+            PURCHASES.save(deps.storage, (next_draw_id, buyer), &purchases)?;
+        }
+
+        // The tokens cannot be updated from here, their metadata keeps naming this draw
+        let nft_tickets = NFT_TICKETS
+            .prefix(draw.id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<(String, Vec<String>)>>>()?;
+
+        for (token_id, tickets) in nft_tickets {
+            NFT_TICKETS.remove(deps.storage, (draw.id, &token_id));
+            NFT_TICKETS.save(deps.storage, (next_draw_id, &token_id), &tickets)?;
+        }
+
+        let mut next_draw = DRAWS.load(deps.storage, next_draw_id)?;
+        next_draw.total_tickets = draw.total_tickets;
+        next_draw.second_chance_pool = draw.second_chance_pool;
+        next_draw.jackpot_contribution = draw.jackpot_contribution;
+        next_draw.randomness_fee_contribution = draw.randomness_fee_contribution;
+        next_draw.referral_rewards = draw.referral_rewards;
+        DRAWS.save(deps.storage, next_draw_id, &next_draw)?;
+    }
+
+    draw.status = Status::Cancelled;
+    DRAWS.save(deps.storage, draw.id, &draw)?;
+
+    let event = Event::new("superstar.v1.MsgCancelDraw")
+        .add_attribute("draw_id", draw.id.to_string())
+        .add_attribute("total_tickets", draw.total_tickets.to_string())
+        .add_attribute("policy", format!("{:?}", policy))
+        .add_attribute("refunds", refunds.to_string())
+        .add_attribute("next_draw_id", next_draw_id.to_string());
+
+    Ok(response.add_event(event))
+}
+
+/// Keeps the oldest IBC payout being sent under the packet sequence of its transfer,
+/// until ibc-hooks reports whether it arrived
+fn track_ibc_payout(deps: DepsMut, result: SubMsgResult) -> Result<Response, ContractError> {
+    let data = result
+        .into_result()
+        .map_err(StdError::generic_err)?
+        .data
+        .ok_or(ContractError::InvalidReply)?;
+    let sequence = transfer_sequence(data.as_slice()).ok_or(ContractError::InvalidReply)?;
+
+    let mut sending = SENDING_IBC_PAYOUTS
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    if sending.is_empty() {
+        return Err(ContractError::InvalidReply);
+    }
+    let payout = sending.remove(0);
+
+    if sending.is_empty() {
+        SENDING_IBC_PAYOUTS.remove(deps.storage);
+    } else {
+        SENDING_IBC_PAYOUTS.save(deps.storage, &sending)?;
+    }
+
+    IBC_PAYOUTS.save(deps.storage, (&payout.channel, sequence), &payout)?;
+
+    let event = Event::new("superstar.v1.MsgSendIbcPayout")
+        .add_attribute("channel", payout.channel)
+        .add_attribute("sequence", sequence.to_string())
+        .add_attribute("winner", payout.winner.to_string())
+        .add_attribute("amount", payout.amount.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+/// Forgets an IBC payout once it arrived. Should the transfer fail or time out, ICS-20
+/// returns the funds to the contract and the payout is credited back to the winner
+fn settle_ibc_payout(
+    deps: DepsMut,
+    channel: String,
+    sequence: u64,
+    delivered: bool,
+) -> Result<Response, ContractError> {
+    let Some(payout) = IBC_PAYOUTS.may_load(deps.storage, (&channel, sequence))? else {
+        return Ok(Response::new());
+    };
+    IBC_PAYOUTS.remove(deps.storage, (&channel, sequence));
+
+    if !delivered {
+        FAILED_PAYOUTS.update(
+            deps.storage,
+            (payout.winner.clone(), &payout.amount.denom),
+            |failed| -> StdResult<Uint128> {
+                Ok(failed
+                    .unwrap_or_default()
+                    .checked_add(payout.amount.amount)?)
+            },
+        )?;
+
+        update_liabilities(deps.storage, |mut liabilities| {
+            liabilities.failed_payouts = liabilities
+                .failed_payouts
+                .checked_add(payout.amount.amount)?;
+            Ok(liabilities)
+        })?;
+    }
+
+    let event = Event::new("superstar.v1.MsgSettleIbcPayout")
+        .add_attribute("channel", channel)
+        .add_attribute("sequence", sequence.to_string())
+        .add_attribute("winner", payout.winner.to_string())
+        .add_attribute("amount", payout.amount.to_string())
+        .add_attribute("delivered", delivered.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+pub fn retry_payouts(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let failed = FAILED_PAYOUTS
+        .prefix(info.sender.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(String, Uint128)>>>()?;
+
+    if failed.is_empty() {
+        return Err(ContractError::NoPrizeToClaim);
+    }
+
+    let mut response = Response::new();
+    let mut total = Uint128::zero();
+
+    for (denom, amount) in &failed {
+        FAILED_PAYOUTS.remove(deps.storage, (info.sender.clone(), denom));
+        total = total.checked_add(*amount)?;
+
+        let payout = coin(amount.u128(), denom);
+        response =
+            response.add_submessage(prize_payout_msg(deps.storage, &env, &info.sender, payout)?);
+    }
+
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.failed_payouts = liabilities.failed_payouts.checked_sub(total)?;
+        Ok(liabilities)
+    })?;
+
+    let event = Event::new("superstar.v1.MsgRetryPayouts")
+        .add_attribute("winner", info.sender.to_string())
+        .add_attribute("amount", total.to_string());
+
+    Ok(response.add_event(event))
+}
+
+/// Pays back the tickets the sender held when their draw was cancelled
+pub fn claim_refund(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    draw_id: u64,
+) -> Result<Response, ContractError> {
+    let draw = DRAWS.load(deps.storage, draw_id)?;
+
+    let refund = REFUNDS
+        .may_load(deps.storage, (draw_id, info.sender.clone()))?
+        .ok_or(ContractError::NoRefund)?;
+    REFUNDS.remove(deps.storage, (draw_id, info.sender.clone()));
+
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.refunds = liabilities.refunds.checked_sub(refund)?;
+        Ok(liabilities)
+    })?;
+
+    let refund = coin(refund.u128(), draw.ticket_price.denom);
+
+    let event = Event::new("superstar.v1.MsgClaimRefund")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("buyer", info.sender.to_string())
+        .add_attribute("refund", refund.to_string());
+
+    Ok(Response::new()
+        .add_submessage(prize_payout_msg(deps.storage, &env, &info.sender, refund)?)
+        .add_event(event))
+}
+
+pub fn receive_randomness(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    callback: NoisCallback,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.nois_proxy, ContractError::Unauthorized);
+
+    if let Some(draw_id) = callback.job_id.strip_prefix(SECOND_CHANCE_JOB_PREFIX) {
+        let draw_id = draw_id.parse::<u64>()?;
+        return receive_second_chance_randomness(deps, config, draw_id, callback.randomness);
+    }
+
+    let draw_id = callback.job_id.parse::<u64>()?;
+
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
+
+    ensure_eq!(
+        draw.status,
+        Status::Pending,
+        ContractError::DrawIsNotPending
+    );
+
+    let randomness: [u8; 32] = callback
+        .randomness
+        .to_array()
+        .map_err(|_| ContractError::InvalidRandomness)?;
+
+    let result = ints_in_range(randomness, 6, 0, 9);
+
+    let winner_number = result
+        .into_iter()
+        .fold(String::new(), |acc, x| acc + &x.to_string());
+
+    draw.winner_number = Some(winner_number.clone());
+    draw.randomness = Some(callback.randomness);
+    draw.status = Status::Raffling;
+
+    DRAWS.save(deps.storage, draw_id, &draw)?;
+    REQUESTS.remove(deps.storage, draw_id);
+
+    let event = Event::new("superstar.v1.MsgReceiveRandomness")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("winner_number", winner_number);
+
+    // Small draws are settled right away, larger ones wait for a manual raffle
+    // so that counting the winners cannot exhaust the callback's gas
+    let auto_raffle = config.no_loss.is_none()
+        && config
+            .auto_raffle_max_tickets
+            .is_some_and(|max_tickets| draw.total_tickets <= max_tickets);
+
+    if !auto_raffle {
+        return Ok(Response::new().add_event(event));
+    }
+
+    let response = raffle_draw(deps, env, config, draw)?;
+
+    Ok(Response::new()
+        .add_event(event)
+        .add_submessages(response.messages)
+        .add_events(response.events))
+}
+
+pub fn request_second_chance(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    draw_id: u64,
+) -> Result<Response, ContractError> {
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
+
+    ensure_eq!(
+        draw.status,
+        Status::Claimable,
+        ContractError::DrawIsNotClaimable
+    );
+
+    match draw.second_chance_status {
+        Some(SecondChanceStatus::Pending) => {}
+        Some(SecondChanceStatus::Requested) => {
+            if let Some(request) = SECOND_CHANCE_REQUESTS.may_load(deps.storage, draw_id)? {
+                if !request.is_expired(&env.block) {
+                    return Err(ContractError::RandomnessAlreadyRequested);
+                }
+            }
+        }
+        _ => return Err(ContractError::NoSecondChance),
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+
+    add_sent_randomness_fees(deps.storage, &deps.querier, &config.nois_proxy, &info.funds)?;
+    let fee = take_randomness_fee(deps.storage, &deps.querier, &config.nois_proxy)?;
+
+    draw.second_chance_status = Some(SecondChanceStatus::Requested);
+    DRAWS.save(deps.storage, draw_id, &draw)?;
+
+    SECOND_CHANCE_REQUESTS.save(
+        deps.storage,
+        draw_id,
+        &build_expiration_time(&env, config.request_timeout)?,
+    )?;
+
+    let msg = wasm_execute(
+        config.nois_proxy.to_string(),
+        &ProxyExecuteMsg::GetNextRandomness {
+            job_id: format!("{}{}", SECOND_CHANCE_JOB_PREFIX, draw_id),
+        },
+        fee,
+    )?;
+
+    let event = Event::new("superstar.v1.MsgRequestSecondChance")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("pool", draw.second_chance_pool.to_string());
+
+    Ok(Response::new().add_event(event).add_message(msg))
+}
+
+/// Keeps the second-chance randomness, the winners are then picked by `raffle_second_chance`
+/// so that going through the tickets cannot exhaust the callback's gas
+fn receive_second_chance_randomness(
+    deps: DepsMut,
+    config: Config,
+    draw_id: u64,
+    randomness: HexBinary,
+) -> Result<Response, ContractError> {
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
+    let second_chance = config
+        .second_chance
+        .as_ref()
+        .ok_or(ContractError::NoSecondChance)?;
+
+    ensure_eq!(
+        draw.second_chance_status,
+        Some(SecondChanceStatus::Requested),
+        ContractError::DrawIsNotPending
+    );
+
+    if randomness.len() != 32 {
+        return Err(ContractError::InvalidRandomness);
+    }
+
+    SECOND_CHANCE_RAFFLES.save(
+        deps.storage,
+        draw_id,
+        &SecondChanceRaffle {
+            randomness,
+            winners: second_chance.winners,
+            prize: second_chance.prize,
+            after: None,
+            picks: vec![],
+        },
+    )?;
+    SECOND_CHANCE_REQUESTS.remove(deps.storage, draw_id);
+
+    draw.second_chance_status = Some(SecondChanceStatus::Raffling);
+    DRAWS.save(deps.storage, draw_id, &draw)?;
+
+    let event = Event::new("superstar.v1.MsgReceiveSecondChance")
+        .add_attribute("draw_id", draw_id.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+/// Goes through a page of ticket holders, keeping the best ranked tickets that matched no
+/// digit. Once every holder is done the kept tickets are paid, and whatever the prizes leave
+/// behind, or the whole pool if there is no such ticket, goes to the open pot
+pub fn raffle_second_chance(
+    deps: DepsMut,
+    env: Env,
+    draw_id: u64,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
+
+    ensure_eq!(
+        draw.second_chance_status,
+        Some(SecondChanceStatus::Raffling),
+        ContractError::DrawIsNotPending
+    );
+
+    let mut raffle = SECOND_CHANCE_RAFFLES.load(deps.storage, draw_id)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let winner_number = draw.winner_number.clone().unwrap_or_default();
+
+    let (page, done) = draw_tickets_page(deps.storage, draw_id, raffle.after.as_ref(), limit)?;
+    let holders = page.len();
+
+    for (holder, tickets) in page {
+        for (index, ticket) in tickets.into_iter().enumerate() {
+            if calculate_matches(&winner_number, &ticket) != 0 {
+                continue;
+            }
+
+            let rank = second_chance_rank(&raffle.randomness, &holder, index, &ticket);
+            let at = raffle.picks.partition_point(|pick| pick.rank < rank);
+
+            if at < raffle.winners as usize {
+                raffle.picks.insert(
+                    at,
+                    SecondChancePick {
+                        rank,
+                        holder: holder.clone(),
+                        ticket,
+                    },
+                );
+                raffle.picks.truncate(raffle.winners as usize);
+            }
+        }
+
+        raffle.after = Some(holder);
+    }
+
+    if !done {
+        SECOND_CHANCE_RAFFLES.save(deps.storage, draw_id, &raffle)?;
+
+        let event = Event::new("superstar.v1.MsgRaffleSecondChance")
+            .add_attribute("draw_id", draw_id.to_string())
+            .add_attribute("holders", holders.to_string());
+
+        return Ok(Response::new().add_event(event));
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    let pool = draw.second_chance_pool;
+
+    // A fixed prize pays as many tickets as the pool covers, otherwise they share it
+    let (n_winners, prize) = match raffle.prize {
+        Some(prize) => {
+            let covered = pool.checked_div(prize)?.u128();
+            (raffle.picks.len().min(covered as usize), prize)
+        }
+        None if raffle.picks.is_empty() => (0, Uint128::zero()),
+        None => (
+            raffle.picks.len(),
+            pool.checked_div(Uint128::from(raffle.picks.len() as u128))?,
+        ),
+    };
+
+    let denom = &draw.total_prize.denom;
+    let mut response = Response::new();
+
+    let mut winners = Vec::with_capacity(n_winners);
+
+    for pick in raffle.picks.into_iter().take(n_winners) {
+        // A burned token or a broken collection must not hold up the others, whatever
+        // the ticket would have won goes back to the pot with the leftover
+        let Some(addr) = ticket_holder_addr(deps.as_ref(), &config, pick.holder) else {
+            continue;
+        };
+
+        winners.push(SecondChanceWinner {
+            addr,
+            ticket: pick.ticket,
+            prize: coin(prize.u128(), denom),
+        });
+    }
+
+    let paid = prize.checked_mul(Uint128::from(winners.len() as u128))?;
+    let leftover = pool.checked_sub(paid)?;
+
+    if !prize.is_zero() {
+        for winner in &winners {
+            response = response.add_submessage(prize_payout_msg(
+                deps.storage,
+                &env,
+                &winner.addr,
+                winner.prize.clone(),
+            )?);
+        }
+    }
+
+    SECOND_CHANCE_WINNERS.save(deps.storage, draw_id, &winners)?;
+    SECOND_CHANCE_RAFFLES.remove(deps.storage, draw_id);
+
+    draw.second_chance_status = Some(SecondChanceStatus::Settled);
+    DRAWS.save(deps.storage, draw_id, &draw)?;
+
+    if !leftover.is_zero() {
+        let current_draw_id = DRAWS_INDEX.load(deps.storage)?;
+        let mut current = DRAWS.load(deps.storage, current_draw_id)?;
+        current.total_prize.amount = current.total_prize.amount.checked_add(leftover)?;
+        update_prize_per_match(deps.storage, &config, &mut current)?;
+        DRAWS.save(deps.storage, current_draw_id, &current)?;
+    }
+
+    // The leftover stays an open pot liability, it just moved to the current draw
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.open_pots = liabilities.open_pots.checked_sub(paid)?;
+        Ok(liabilities)
+    })?;
+
+    let event = Event::new("superstar.v1.MsgRaffleSecondChance")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("holders", holders.to_string())
+        .add_attribute("winners", winners.len().to_string())
+        .add_attribute("prize", prize.to_string())
+        .add_attribute("leftover", leftover.to_string());
+
+    Ok(response.add_event(event))
+}
+
+pub fn raffle(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    draw_id: u64,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let mut draw = DRAWS.load(deps.storage, draw_id)?;
+
+    ensure_eq!(
+        draw.status,
+        Status::Raffling,
+        ContractError::DrawIsNotClaimable
+    );
+
+    let config = CONFIG.load(deps.storage)?;
+
+    // A no-loss draw raffled over several pages pays the bounty for its first page
+    let already_paid = draw.raffle_bounty.is_some();
+    let keeper_bounty = take_keeper_bounty(deps.storage, &config, &mut draw, already_paid)?;
+    if keeper_bounty.is_some() {
+        draw.raffle_bounty = keeper_bounty.clone();
+    }
+
+    let response = if config.no_loss.is_some() {
+        raffle_no_loss(deps, env, config, draw, limit)?
+    } else {
+        raffle_draw(deps, env, config, draw)?
+    };
+
+    Ok(match keeper_bounty {
+        Some(bounty) if !bounty.amount.is_zero() => {
+            let event = Event::new("superstar.v1.MsgPayKeeperBounty")
+                .add_attribute("draw_id", draw_id.to_string())
+                .add_attribute("keeper", info.sender.to_string())
+                .add_attribute("bounty", bounty.to_string());
+
+            response
+                .add_message(BankMsg::Send {
+                    to_address: info.sender.to_string(),
+                    amount: vec![bounty],
+                })
+                .add_event(event)
+        }
+        _ => response,
+    })
+}
+
+/// Settles the winners of a draw whose winning number is known
+fn raffle_draw(
+    deps: DepsMut,
+    env: Env,
+    config: Config,
+    mut draw: Draw,
+) -> Result<Response, ContractError> {
+    let draw_id = draw.id;
+    let purchases = draw_tickets(deps.storage, draw_id)?;
+
+    let winner_number = draw.winner_number.clone().unwrap_or_default();
+
+    let winners_per_match = calculate_winner_per_match(purchases, winner_number.clone())?;
+
+    let tiers = config.tiers();
+
+    let fixed_prizes_scaled =
+        calculate_fixed_prizes_needed(tiers, winners_per_match)? > draw.total_prize.amount;
+
+    draw.winners_per_match = Some(winners_per_match);
+
+    update_prize_per_match(deps.storage, &config, &mut draw)?;
+
+    let mut prize_per_match = draw.prize_per_match.unwrap_or_default();
+
+    // The jackpot subsidy only leaves the reserve when somebody wins it,
+    // otherwise it must not be rolled over as if it was part of the pot
+    let jackpot_won = winners_per_match[5] > 0;
+
+    if jackpot_won && !draw.jackpot_subsidy.is_zero() {
+        RESERVE.update(deps.storage, |reserve| -> StdResult<Uint128> {
+            Ok(reserve.checked_sub(draw.jackpot_subsidy)?)
+        })?;
+    }
+
+    let subsidy_used = if jackpot_won {
+        draw.jackpot_subsidy
+    } else {
+        Uint128::zero()
+    };
+
+    let mut overflow_rollover = Uint128::zero();
+    let mut overflow_to_reserve = Uint128::zero();
+
+    if !draw.jackpot_overflow.is_zero() {
+        match config.jackpot_cap.as_ref().map(|cap| &cap.overflow) {
+            Some(OverflowPolicy::Reserve) => {
+                let reserve = RESERVE.may_load(deps.storage)?.unwrap_or_default();
+                RESERVE.save(deps.storage, &reserve.checked_add(draw.jackpot_overflow)?)?;
+                overflow_to_reserve = draw.jackpot_overflow;
+            }
+            Some(OverflowPolicy::LowerTierWinners) => {
+                let distributed = distribute_overflow(
+                    &mut prize_per_match,
+                    winners_per_match,
+                    draw.jackpot_overflow,
+                    tiers,
+                )?;
+                overflow_rollover = draw.jackpot_overflow.checked_sub(distributed)?;
+            }
+            // Already added to the next lower tier by the prize distribution
+            Some(OverflowPolicy::NextTier) | None => {}
+        }
+    }
+
+    // The progressive jackpot builds up across draws until somebody matches all six digits
+    if jackpot_won {
+        draw.progressive_jackpot = JACKPOT.may_load(deps.storage)?.unwrap_or_default();
+        prize_per_match[5] = prize_per_match[5].checked_add(draw.progressive_jackpot)?;
+        JACKPOT.save(deps.storage, &Uint128::zero())?;
+    }
+
+    let accumulative_pot = prize_per_match.iter().enumerate().try_fold(
+        Uint128::zero(),
+        |acc, (i, x)| -> Result<Uint128, ContractError> {
+            if winners_per_match[i].eq(&0) {
+                Ok(acc.checked_add(*x)?)
+            } else {
+                Ok(acc)
+            }
+        },
+    )?;
+
+    let accumulative_pot = if jackpot_won {
+        accumulative_pot
+    } else {
+        accumulative_pot.checked_sub(draw.jackpot_subsidy)?
+    }
+    .checked_add(overflow_rollover)?;
+
+    // Whatever truncation left behind, either from the distribution or from splitting a
+    // tier between its winners, is rolled over so every coin of the pot is accounted for
+    let payouts = calculate_payouts(prize_per_match, winners_per_match)?;
+
+    draw.dust = draw
+        .total_prize
+        .amount
+        .checked_add(subsidy_used)?
+        .checked_add(draw.progressive_jackpot)?
+        .checked_sub(payouts)?
+        .checked_sub(overflow_to_reserve)?
+        .checked_sub(accumulative_pot)?;
+
+    let treasury_fee =
+        accumulative_pot.checked_multiply_ratio(config.treasury_fee, Uint128::from(100u128))?;
+
+    let next_prize = accumulative_pot
+        .checked_sub(treasury_fee)?
+        .checked_add(draw.dust)?;
+
+    // Referral credits only ever come out of the treasury fee, should the fee fall
+    // short this draw's credits are scaled down when they are claimed
+    draw.referral_funded = draw.referral_rewards.min(treasury_fee);
+    let treasury_fee = treasury_fee.checked_sub(draw.referral_funded)?;
+    draw.status = Status::Claimable;
+    draw.prize_per_match = Some(prize_per_match);
+
+    if !draw.second_chance_pool.is_zero() {
+        draw.second_chance_status = Some(SecondChanceStatus::Pending);
+    }
+
+    DRAWS.save(deps.storage, draw_id, &draw)?;
+
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.open_pots = liabilities
+            .open_pots
+            .checked_sub(draw.total_prize.amount)?
+            .checked_add(next_prize)?;
+        liabilities.unclaimed_prizes = liabilities.unclaimed_prizes.checked_add(payouts)?;
+        liabilities.accrued_fees = liabilities.accrued_fees.checked_add(draw.referral_funded)?;
+        Ok(liabilities)
+    })?;
+
+    let mut response: Response = Response::new();
+
+    if !treasury_fee.is_zero() {
+        response = response.add_message(CosmosMsg::Bank(BankMsg::Send {
+            to_address: deps.api.addr_humanize(&config.owner)?.to_string(),
+            amount: vec![coin(treasury_fee.u128(), config.ticket_price.denom)],
+        }));
+    }
+
+    create_next_draw(deps, &env, next_prize)?;
+
+    let event = Event::new("superstar.v1.MsgRaffle")
+        .add_attribute("draw_id", draw_id.to_string())
+        .add_attribute("winner_number", winner_number.to_string())
+        .add_attribute("jackpot_subsidy", subsidy_used.to_string())
+        .add_attribute("jackpot_overflow", draw.jackpot_overflow.to_string())
+        .add_attribute("progressive_jackpot", draw.progressive_jackpot.to_string())
+        .add_attribute("overflow_rollover", overflow_rollover.to_string())
+        .add_attribute("fixed_prizes_scaled", fixed_prizes_scaled.to_string())
+        .add_attribute("payouts", payouts.to_string())
+        .add_attribute("treasury_fee", treasury_fee.to_string())
+        .add_attribute("referral_rewards", draw.referral_funded.to_string())
+        .add_attribute("dust", draw.dust.to_string())
+        .add_attribute("rollover", next_prize.to_string());
+
+    Ok(response.add_event(event))
+}
+
+/// Goes through a page of depositors, picking one by weight. Once every depositor is done
+/// the whole pot, minus the treasury fee, is paid to the one picked
+fn raffle_no_loss(
+    deps: DepsMut,
+    env: Env,
+    config: Config,
+    mut draw: Draw,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let randomness: [u8; 32] = draw
+        .randomness
+        .as_ref()
+        .ok_or(ContractError::InvalidRandomness)?
+        .to_array()
+        .map_err(|_| ContractError::InvalidRandomness)?;
+
+    let mut raffle = NO_LOSS_RAFFLES
+        .may_load(deps.storage, draw.id)?
+        .unwrap_or_default();
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    // Weights stop growing once the draw ended, so they hold still between pages
+    let mut deposits = DEPOSITS
+        .range(
+            deps.storage,
+            raffle.after.clone().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit.saturating_add(1))
+        .collect::<StdResult<Vec<(Addr, Deposit)>>>()?;
+    let done = deposits.len() <= limit;
+    deposits.truncate(limit);
+    let depositors = deposits.len();
+
+    for (depositor, deposit) in deposits {
+        let weight = accrue_deposit_weight(&deposit, &draw, env.block.time)?.weight;
+
+        // Fully withdrawn deposits are of no use once their last draw is raffled
+        if deposit.principal.is_zero() {
+            DEPOSITS.remove(deps.storage, depositor.clone());
+        }
+
+        if !weight.is_zero() {
+            raffle.total_weight = raffle.total_weight.checked_add(weight)?;
+
+            if takes_weighted_pick(&randomness, &depositor, weight, raffle.total_weight) {
+                raffle.winner = Some(depositor.clone());
+            }
+        }
+
+        raffle.after = Some(depositor);
+    }
+
+    if !done {
+        NO_LOSS_RAFFLES.save(deps.storage, draw.id, &raffle)?;
+        DRAWS.save(deps.storage, draw.id, &draw)?;
+
+        let event = Event::new("superstar.v1.MsgRaffleNoLoss")
+            .add_attribute("draw_id", draw.id.to_string())
+            .add_attribute("depositors", depositors.to_string());
+
+        return Ok(Response::new().add_event(event));
+    }
+
+    NO_LOSS_RAFFLES.remove(deps.storage, draw.id);
+
+    let pot = draw.total_prize.amount;
+    let winner = raffle.winner;
+
+    let mut response = Response::new();
+    let mut treasury_fee = Uint128::zero();
+    let mut prize = Uint128::zero();
+    let mut next_prize = pot;
+
+    // Nobody held a deposit during the draw, so the pot rolls over untouched
+    if let Some(winner) = &winner {
+        treasury_fee = pot.checked_multiply_ratio(config.treasury_fee, Uint128::from(100u128))?;
+        prize = pot.checked_sub(treasury_fee)?;
+        next_prize = Uint128::zero();
+
+        WINNERS.save(
+            deps.storage,
+            (draw.id, winner.clone()),
+            &coin(prize.u128(), &config.ticket_price.denom),
+        )?;
+
+        if !prize.is_zero() {
+            response = response.add_submessage(prize_payout_msg(
+                deps.storage,
+                &env,
+                winner,
+                coin(prize.u128(), &config.ticket_price.denom),
+            )?);
+        }
+
+        if !treasury_fee.is_zero() {
+            response = response.add_message(BankMsg::Send {
+                to_address: deps.api.addr_humanize(&config.owner)?.to_string(),
+                amount: vec![coin(treasury_fee.u128(), &config.ticket_price.denom)],
+            });
+        }
+    }
+
+    draw.status = Status::Claimable;
+    DRAWS.save(deps.storage, draw.id, &draw)?;
+
+    update_liabilities(deps.storage, |mut liabilities| {
+        liabilities.open_pots = liabilities
+            .open_pots
+            .checked_sub(pot)?
+            .checked_add(next_prize)?;
+        Ok(liabilities)
+    })?;
+
+    create_next_draw(deps, &env, next_prize)?;
+
+    let event = Event::new("superstar.v1.MsgRaffle")
+        .add_attribute("draw_id", draw.id.to_string())
+        .add_attribute("winner", winner.map(|w| w.to_string()).unwrap_or_default())
+        .add_attribute("prize", prize.to_string())
+        .add_attribute("treasury_fee", treasury_fee.to_string())
+        .add_attribute("rollover", next_prize.to_string());
+
+    Ok(response.add_event(event))
+}
+
+pub fn recover_surplus(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    recipient: Option<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != deps.api.addr_humanize(&config.owner)? {
+        return Err(ContractError::Unauthorized);
+    }
+
+    let balance = deps
+        .querier
+        .query_balance(&env.contract.address, &config.ticket_price.denom)?;
+
+    let solvency = calculate_solvency(deps.storage, balance, env.block.time)?;
+
+    if amount.is_zero() || amount > solvency.surplus {
+        return Err(ContractError::InsufficientSurplus);
+    }
+
+    let recipient = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => info.sender,
+    };
+
+    let event = Event::new("superstar.v1.MsgRecoverSurplus")
+        .add_attribute("recipient", recipient.to_string())
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("surplus", solvency.surplus.to_string());
+
+    Ok(Response::new()
+        .add_message(CosmosMsg::Bank(BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![coin(amount.u128(), config.ticket_price.denom)],
+        }))
+        .add_event(event))
+}
+
+pub fn update_config(
+    deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     config: UpdateConfigMsg,
 ) -> Result<Response, ContractError> {
@@ -356,58 +2505,169 @@ pub fn update_config(
         return Err(ContractError::Unauthorized);
     }
 
-    if let Some(new_interval) = config.interval {
-        current_config.interval = new_interval;
+    // Only the emergency switch skips the timelock, everything else must be proposed
+    if current_config.config_timelock.is_some() && !config.is_emergency_only() {
+        return Err(ContractError::ConfigChangeIsTimelocked);
     }
 
-    if let Some(new_nois_proxy) = config.nois_proxy {
-        current_config.nois_proxy = deps.api.addr_validate(new_nois_proxy.as_str())?;
-    }
+    let old_config = current_config.clone();
+    apply_config_changes(deps.api, &mut current_config, config)?;
+    ensure_no_loss_denom_is_bonded(&deps.querier, &current_config)?;
+
+    CONFIG.save(deps.storage, &current_config)?;
+
+    let changes = record_config_change(
+        deps.storage,
+        &env,
+        info.sender,
+        &old_config,
+        &current_config,
+    )?;
+
+    let event = Event::new("superstar.v1.MsgUpdateConfig").add_attributes(
+        changes
+            .into_iter()
+            .map(|c| (c.field, format!("{} -> {}", c.old, c.new))),
+    );
+
+    Ok(Response::new().add_event(event))
+}
+
+pub fn propose_config_change(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    new_config: UpdateConfigMsg,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
 
-    if let Some(request_timeout) = config.request_timeout {
-        current_config.request_timeout = request_timeout;
+    if info.sender != deps.api.addr_humanize(&config.owner)? {
+        return Err(ContractError::Unauthorized);
     }
 
-    if let Some(new_treasury_fee) = config.treasury_fee {
-        current_config.treasury_fee = new_treasury_fee;
+    // Reject invalid changes now rather than once the timelock is over
+    let mut proposed = config.clone();
+    apply_config_changes(deps.api, &mut proposed, new_config.clone())?;
+    ensure_no_loss_denom_is_bonded(&deps.querier, &proposed)?;
+
+    let activates_at =
+        build_expiration_time(&env, config.config_timelock.unwrap_or(Duration::Time(0)))?;
+
+    PENDING_CONFIG.save(
+        deps.storage,
+        &PendingConfig {
+            config: new_config,
+            activates_at,
+        },
+    )?;
+
+    let event = Event::new("superstar.v1.MsgProposeConfigChange")
+        .add_attribute("activates_at", activates_at.to_string());
+
+    Ok(Response::new().add_event(event))
+}
+
+pub fn apply_config_change(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+
+    if info.sender != deps.api.addr_humanize(&config.owner)? {
+        return Err(ContractError::Unauthorized);
     }
 
-    if let Some(new_percentage_per_match) = config.percentage_per_match {
-        current_config.percentage_per_match = new_percentage_per_match;
+    let pending = PENDING_CONFIG
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoPendingConfigChange)?;
+
+    if !pending.activates_at.is_expired(&env.block) {
+        return Err(ContractError::ConfigChangeIsTimelocked);
     }
 
-    if let Some(new_ticket_price) = config.ticket_price {
-        current_config.ticket_price = new_ticket_price;
+    let old_config = config.clone();
+    apply_config_changes(deps.api, &mut config, pending.config)?;
+    ensure_no_loss_denom_is_bonded(&deps.querier, &config)?;
+
+    CONFIG.save(deps.storage, &config)?;
+    PENDING_CONFIG.remove(deps.storage);
+
+    let changes = record_config_change(deps.storage, &env, info.sender, &old_config, &config)?;
+
+    let event = Event::new("superstar.v1.MsgApplyConfigChange").add_attributes(
+        changes
+            .into_iter()
+            .map(|c| (c.field, format!("{} -> {}", c.old, c.new))),
+    );
+
+    Ok(Response::new().add_event(event))
+}
+
+pub fn cancel_config_change(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != deps.api.addr_humanize(&config.owner)? {
+        return Err(ContractError::Unauthorized);
     }
 
-    if let Some(new_max_tickets_per_user) = config.max_tickets_per_user {
-        current_config.max_tickets_per_user = new_max_tickets_per_user;
+    if PENDING_CONFIG.may_load(deps.storage)?.is_none() {
+        return Err(ContractError::NoPendingConfigChange);
     }
 
-    CONFIG.save(deps.storage, &current_config)?;
+    PENDING_CONFIG.remove(deps.storage);
 
-    let event = Event::new("superstar.v1.MsgUpdateConfig");
+    let event = Event::new("superstar.v1.MsgCancelConfigChange");
 
     Ok(Response::new().add_event(event))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetCurrentDraw {} => to_binary(&get_current_draw(deps)?),
         QueryMsg::GetDraw { id } => to_binary(&get_draw(deps, id)?),
         QueryMsg::CheckWinner { addr, draw_id } => to_binary(&check_winner(deps, addr, draw_id)?),
         QueryMsg::GetTickets { addr, draw_id } => to_binary(&get_tickets(deps, draw_id, addr)?),
         QueryMsg::GetConfig {} => to_binary(&get_config(deps)?),
+        QueryMsg::GetSponsors { draw_id } => to_binary(&get_sponsors(deps, draw_id)?),
+        QueryMsg::GetReserve {} => to_binary(&get_reserve(deps)?),
+        QueryMsg::GetSolvency {} => to_binary(&get_solvency(deps, env)?),
+        QueryMsg::GetDeposit { addr } => to_binary(&get_deposit(deps, env, addr)?),
+        QueryMsg::GetPendingConfig {} => to_binary(&get_pending_config(deps)?),
+        QueryMsg::GetConfigHistory { start_after, limit } => {
+            to_binary(&get_config_history(deps, start_after, limit)?)
+        }
+        QueryMsg::QuoteTickets { n_tickets } => to_binary(&get_ticket_quote(deps, env, n_tickets)?),
+        QueryMsg::GetPurchases { addr, draw_id } => to_binary(&get_purchases(deps, draw_id, addr)?),
+        QueryMsg::GetReferralStats { addr } => to_binary(&get_referral_stats(deps, addr)?),
+        QueryMsg::GetPromoBudget {} => to_binary(&get_promo_budget(deps)?),
+        QueryMsg::GetLoyaltyPoints { addr } => to_binary(&get_loyalty_points(deps, addr)?),
+        QueryMsg::GetLoyaltyReserve {} => to_binary(&get_loyalty_reserve(deps)?),
+        QueryMsg::GetJackpot {} => to_binary(&get_jackpot(deps)?),
+        QueryMsg::GetGamingLimits { addr } => to_binary(&get_gaming_limits(deps, env, addr)?),
+        QueryMsg::GetRefund { draw_id, addr } => to_binary(&get_refund(deps, draw_id, addr)?),
+        QueryMsg::GetSchedule { limit } => to_binary(&get_schedule(deps, env, limit)?),
+        QueryMsg::GetRandomnessFees {} => to_binary(&get_randomness_fees(deps)?),
+        QueryMsg::GetFailedPayouts { addr } => to_binary(&get_failed_payouts(deps, addr)?),
+        QueryMsg::IsAllowed { addr } => to_binary(&is_allowed(deps, addr)?),
+        QueryMsg::GetRemoteAccount { addr } => to_binary(&get_remote_account(deps, addr)?),
+        QueryMsg::GetNftTickets { draw_id, token_id } => {
+            to_binary(&get_nft_tickets(deps, draw_id, token_id)?)
+        }
+        QueryMsg::GetSecondChanceWinners { draw_id } => {
+            to_binary(&get_second_chance_winners(deps, draw_id)?)
+        }
+        QueryMsg::IsVoucherUsed { nonce } => to_binary(&USED_VOUCHERS.has(deps.storage, &nonce)),
     }
 }
 
 pub fn get_current_draw(deps: Deps) -> StdResult<Draw> {
-    Ok(DRAWS.load(deps.storage, DRAWS_INDEX.load(deps.storage)?)?)
+    DRAWS.load(deps.storage, DRAWS_INDEX.load(deps.storage)?)
 }
 
 pub fn get_draw(deps: Deps, id: u64) -> StdResult<Option<Draw>> {
-    Ok(DRAWS.may_load(deps.storage, id)?)
+    DRAWS.may_load(deps.storage, id)
 }
 
 pub fn get_tickets(deps: Deps, draw_id: u64, addr: String) -> StdResult<Vec<String>> {
@@ -420,7 +2680,257 @@ pub fn get_tickets(deps: Deps, draw_id: u64, addr: String) -> StdResult<Vec<Stri
 }
 
 pub fn get_config(deps: Deps) -> StdResult<Config> {
-    Ok(CONFIG.load(deps.storage)?)
+    CONFIG.load(deps.storage)
+}
+
+pub fn get_pending_config(deps: Deps) -> StdResult<Option<PendingConfig>> {
+    PENDING_CONFIG.may_load(deps.storage)
+}
+
+pub fn get_config_history(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<ConfigHistoryEntry>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    CONFIG_HISTORY
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, entry)| entry))
+        .collect()
+}
+
+pub fn get_ticket_quote(deps: Deps, env: Env, n_tickets: u32) -> StdResult<PriceQuote> {
+    let config = CONFIG.load(deps.storage)?;
+
+    quote_tickets(
+        &config.ticket_price,
+        config.pricing.as_ref(),
+        n_tickets,
+        env.block.time,
+    )
+    .map_err(|e| StdError::generic_err(e.to_string()))
+}
+
+pub fn get_purchases(deps: Deps, draw_id: u64, addr: String) -> StdResult<Vec<Purchase>> {
+    Ok(PURCHASES
+        .may_load(
+            deps.storage,
+            (draw_id, deps.api.addr_validate(addr.as_str())?),
+        )?
+        .unwrap_or_default())
+}
+
+pub fn get_referral_stats(deps: Deps, addr: String) -> StdResult<ReferralStatsResponse> {
+    let addr = deps.api.addr_validate(&addr)?;
+
+    let credits = REFERRAL_CREDITS
+        .prefix(addr.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(u64, Uint128)>>>()?;
+
+    let mut claimable = Uint128::zero();
+    let mut unfunded = Uint128::zero();
+    let mut pending = Uint128::zero();
+
+    for (draw_id, credit) in credits {
+        let draw = DRAWS.load(deps.storage, draw_id)?;
+
+        if draw.status == Status::Claimable {
+            let funded = credit
+                .checked_multiply_ratio(draw.referral_funded, draw.referral_rewards)
+                .map_err(|e| StdError::generic_err(e.to_string()))?;
+            claimable = claimable.checked_add(funded)?;
+            unfunded = unfunded.checked_add(credit.checked_sub(funded)?)?;
+        } else {
+            pending = pending.checked_add(credit)?;
+        }
+    }
+
+    let mut stats = REFERRAL_STATS
+        .may_load(deps.storage, addr.clone())?
+        .unwrap_or_default();
+    stats.total_earned = stats.total_earned.checked_sub(unfunded)?;
+
+    Ok(ReferralStatsResponse {
+        referrer: REFERRERS.may_load(deps.storage, addr)?,
+        stats,
+        claimable,
+        pending,
+    })
+}
+
+pub fn get_sponsors(deps: Deps, draw_id: u64) -> StdResult<Vec<Sponsorship>> {
+    SPONSORS
+        .prefix(draw_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(sponsor, amount)| Sponsorship { sponsor, amount }))
+        .collect()
+}
+
+pub fn get_reserve(deps: Deps) -> StdResult<Coin> {
+    let config = CONFIG.load(deps.storage)?;
+    let reserve = RESERVE.may_load(deps.storage)?.unwrap_or_default();
+
+    Ok(coin(reserve.u128(), config.ticket_price.denom))
+}
+
+pub fn get_second_chance_winners(deps: Deps, draw_id: u64) -> StdResult<Vec<SecondChanceWinner>> {
+    Ok(SECOND_CHANCE_WINNERS
+        .may_load(deps.storage, draw_id)?
+        .unwrap_or_default())
+}
+
+pub fn get_loyalty_points(deps: Deps, addr: String) -> StdResult<LoyaltyPoints> {
+    Ok(LOYALTY_POINTS
+        .may_load(deps.storage, deps.api.addr_validate(&addr)?)?
+        .unwrap_or_default())
+}
+
+pub fn get_gaming_limits(deps: Deps, env: Env, addr: String) -> StdResult<GamingLimitsResponse> {
+    let addr = deps.api.addr_validate(&addr)?;
+    let limits = GAMING_LIMITS
+        .may_load(deps.storage, addr.clone())?
+        .unwrap_or_default();
+    let limits = gaming_limits_at(limits, env.block.time);
+    let ledger = SPEND_LEDGER
+        .may_load(deps.storage, addr)?
+        .unwrap_or_default();
+
+    let (spent_last_day, spent_last_week) = recent_spend(&ledger, env.block.time)?;
+
+    Ok(GamingLimitsResponse {
+        next_purchase_at: next_purchase_at(&limits, &ledger, env.block.time),
+        limits,
+        spent_last_day,
+        spent_last_week,
+    })
+}
+
+pub fn get_refund(deps: Deps, draw_id: u64, addr: String) -> StdResult<Coin> {
+    let draw = DRAWS.load(deps.storage, draw_id)?;
+    let refund = REFUNDS
+        .may_load(deps.storage, (draw_id, deps.api.addr_validate(&addr)?))?
+        .unwrap_or_default();
+
+    Ok(coin(refund.u128(), draw.ticket_price.denom))
+}
+
+/// Next draw end times, exact when the schedule is anchored and estimated from the
+/// current draw otherwise
+pub fn get_schedule(deps: Deps, env: Env, limit: Option<u32>) -> StdResult<Vec<Expiration>> {
+    let config = CONFIG.load(deps.storage)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let first = match config.schedule_anchor {
+        Some(_) => {
+            next_draw_end_time(&config, &env).map_err(|e| StdError::generic_err(e.to_string()))?
+        }
+        None => {
+            DRAWS
+                .load(deps.storage, DRAWS_INDEX.load(deps.storage)?)?
+                .end_time
+        }
+    };
+
+    let mut schedule = Vec::with_capacity(limit);
+    let mut end_time = first;
+
+    while schedule.len() < limit {
+        schedule.push(end_time);
+        end_time = (end_time + config.interval)?;
+    }
+
+    Ok(schedule)
+}
+
+pub fn get_randomness_fees(deps: Deps) -> StdResult<Vec<Coin>> {
+    RANDOMNESS_FEES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| coin(amount.u128(), denom)))
+        .collect()
+}
+
+pub fn get_failed_payouts(deps: Deps, addr: String) -> StdResult<Vec<Coin>> {
+    FAILED_PAYOUTS
+        .prefix(deps.api.addr_validate(&addr)?)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| coin(amount.u128(), denom)))
+        .collect()
+}
+
+pub fn is_allowed(deps: Deps, addr: String) -> StdResult<bool> {
+    let config = CONFIG.load(deps.storage)?;
+    let addr = deps.api.addr_validate(&addr)?;
+
+    Ok(ensure_address_is_allowed(deps, &config, &addr).is_ok())
+}
+
+pub fn get_remote_account(deps: Deps, addr: String) -> StdResult<Option<RemoteAccount>> {
+    REMOTE_ACCOUNTS.may_load(deps.storage, deps.api.addr_validate(&addr)?)
+}
+
+pub fn get_nft_tickets(deps: Deps, draw_id: u64, token_id: String) -> StdResult<Vec<String>> {
+    Ok(NFT_TICKETS
+        .may_load(deps.storage, (draw_id, &token_id))?
+        .unwrap_or_default())
+}
+
+pub fn get_jackpot(deps: Deps) -> StdResult<Coin> {
+    let config = CONFIG.load(deps.storage)?;
+    let jackpot = JACKPOT.may_load(deps.storage)?.unwrap_or_default();
+
+    Ok(coin(jackpot.u128(), config.ticket_price.denom))
+}
+
+pub fn get_loyalty_reserve(deps: Deps) -> StdResult<Coin> {
+    let config = CONFIG.load(deps.storage)?;
+    let loyalty_reserve = LOYALTY_RESERVE.may_load(deps.storage)?.unwrap_or_default();
+
+    Ok(coin(loyalty_reserve.u128(), config.ticket_price.denom))
+}
+
+pub fn get_promo_budget(deps: Deps) -> StdResult<Coin> {
+    let config = CONFIG.load(deps.storage)?;
+    let promo_budget = PROMO_BUDGET.may_load(deps.storage)?.unwrap_or_default();
+
+    Ok(coin(promo_budget.u128(), config.ticket_price.denom))
+}
+
+pub fn get_solvency(deps: Deps, env: Env) -> StdResult<SolvencyResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let balance = deps
+        .querier
+        .query_balance(env.contract.address, config.ticket_price.denom)?;
+
+    calculate_solvency(deps.storage, balance, env.block.time)
+        .map_err(|e| StdError::generic_err(e.to_string()))
+}
+
+pub fn get_deposit(deps: Deps, env: Env, addr: String) -> StdResult<DepositResponse> {
+    let addr = deps.api.addr_validate(&addr)?;
+    let draw = DRAWS.load(deps.storage, DRAWS_INDEX.load(deps.storage)?)?;
+
+    let (principal, weight) = match DEPOSITS.may_load(deps.storage, addr.clone())? {
+        Some(deposit) => {
+            let deposit = accrue_deposit_weight(&deposit, &draw, env.block.time)
+                .map_err(|e| StdError::generic_err(e.to_string()))?;
+            (deposit.principal, deposit.weight)
+        }
+        None => (Uint128::zero(), Uint128::zero()),
+    };
+
+    Ok(DepositResponse {
+        principal,
+        weight,
+        unbonding: UNBONDING.may_load(deps.storage, addr)?.unwrap_or_default(),
+    })
 }
 
 pub fn check_winner(deps: Deps, addr: String, draw_id: u64) -> StdResult<Vec<TicketResult>> {
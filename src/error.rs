@@ -1,6 +1,6 @@
 use std::num::ParseIntError;
 
-use cosmwasm_std::{DivideByZeroError, StdError};
+use cosmwasm_std::{CheckedMultiplyRatioError, DivideByZeroError, OverflowError, StdError};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -11,6 +11,12 @@ pub enum ContractError {
     #[error("{0}")]
     DividedByZero(#[from] DivideByZeroError),
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
+    #[error("{0}")]
+    CheckedMultiplyRatio(#[from] CheckedMultiplyRatioError),
+
     #[error("{0}")]
     ParseIntError(#[from] ParseIntError),
 
@@ -38,9 +44,15 @@ pub enum ContractError {
     #[error("InvalidTicket")]
     InvalidTicket,
 
+    #[error("NoTickets")]
+    NoTickets,
+
     #[error("InsufficientFunds")]
     InsufficientFunds,
 
+    #[error("ExcessFunds")]
+    ExcessFunds,
+
     #[error("InvalidRandomness")]
     InvalidRandomness,
 
@@ -52,4 +64,109 @@ pub enum ContractError {
 
     #[error("NoPrizeToClaim")]
     NoPrizeToClaim,
+
+    #[error("InvalidPrizeTiers")]
+    InvalidPrizeTiers,
+
+    #[error("InsufficientSurplus")]
+    InsufficientSurplus,
+
+    #[error("InvalidGameMode")]
+    InvalidGameMode,
+
+    #[error("NothingUnbonded")]
+    NothingUnbonded,
+
+    #[error("ContractPaused")]
+    ContractPaused,
+
+    #[error("ConfigChangeIsTimelocked")]
+    ConfigChangeIsTimelocked,
+
+    #[error("NoPendingConfigChange")]
+    NoPendingConfigChange,
+
+    #[error("InvalidPricing")]
+    InvalidPricing,
+
+    #[error("InvalidBps")]
+    InvalidBps,
+
+    #[error("InvalidReferrer")]
+    InvalidReferrer,
+
+    #[error("NoReferralRewards")]
+    NoReferralRewards,
+
+    #[error("InvalidVoucher")]
+    InvalidVoucher,
+
+    #[error("VoucherExpired")]
+    VoucherExpired,
+
+    #[error("VoucherAlreadyUsed")]
+    VoucherAlreadyUsed,
+
+    #[error("PromoBudgetExhausted")]
+    PromoBudgetExhausted,
+
+    #[error("InvalidLoyaltyConfig")]
+    InvalidLoyaltyConfig,
+
+    #[error("InsufficientPoints")]
+    InsufficientPoints,
+
+    #[error("LoyaltyReserveExhausted")]
+    LoyaltyReserveExhausted,
+
+    #[error("NoSecondChance")]
+    NoSecondChance,
+
+    #[error("InvalidSecondChance")]
+    InvalidSecondChance,
+
+    #[error("SecondChanceNotSettled")]
+    SecondChanceNotSettled,
+
+    #[error("TicketNotOwned")]
+    TicketNotOwned,
+
+    #[error("InvalidRecipient")]
+    InvalidRecipient,
+
+    #[error("NoTicketNft")]
+    NoTicketNft,
+
+    #[error("InvalidRemoteSender")]
+    InvalidRemoteSender,
+
+    #[error("AddressNotAllowed")]
+    AddressNotAllowed,
+
+    #[error("SelfExcluded")]
+    SelfExcluded,
+
+    #[error("InvalidExclusion")]
+    InvalidExclusion,
+
+    #[error("PurchaseCooldown")]
+    PurchaseCooldown,
+
+    #[error("SpendingLimitExceeded")]
+    SpendingLimitExceeded,
+
+    #[error("DrawSoldOut")]
+    DrawSoldOut,
+
+    #[error("NoRefund")]
+    NoRefund,
+
+    #[error("InvalidSchedule")]
+    InvalidSchedule,
+
+    #[error("RandomnessFeesUnderfunded")]
+    RandomnessFeesUnderfunded,
+
+    #[error("InvalidReply")]
+    InvalidReply,
 }
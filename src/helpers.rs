@@ -1,11 +1,51 @@
-use cosmwasm_std::{coin, Addr, Coin, DepsMut, Env, StdResult, Uint128};
+use bech32::{ToBase32, Variant};
+use cosmwasm_std::{
+    coin, to_vec, Addr, Api, BankMsg, Coin, Deps, DepsMut, Env, HexBinary, Order, OverflowError,
+    OverflowOperation, QuerierWrapper, StdResult, Storage, SubMsg, Timestamp, Uint128,
+};
+use cw_storage_plus::Bound;
 use cw_utils::{Duration, Expiration};
+use nois::int_in_range;
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
 use std::ops::Add;
 
 use crate::error::ContractError;
-use crate::state::{Draw, TicketResult, CONFIG, DRAWS, DRAWS_INDEX};
+use crate::ibc::transfer_msg;
+use crate::msg::{
+    Cw4QueryMsg, Cw721QueryMsg, MemberResponse, NoisPricesResponse, NoisProxyQueryMsg,
+    OwnerOfResponse, SolvencyResponse, UpdateConfigMsg, Voucher, VoucherPayload,
+};
+use crate::pricing::ensure_pricing_is_valid;
+use crate::state::{
+    Allowlist, Config, ConfigChange, ConfigHistoryEntry, Deposit, Draw, GamingLimits, IbcPayout,
+    KeeperBounty, Liabilities, LoyaltyConfig, OverflowPolicy, PendingGamingLimits, PrizeTier,
+    Purchase, SecondChanceConfig, SecondChanceStatus, Spend, Status, TicketHolder, TicketResult,
+    ALLOWLIST, CONFIG, CONFIG_HISTORY, DENYLIST, DRAWS, DRAWS_INDEX, GAMING_LIMITS, JACKPOT,
+    LIABILITIES, LOYALTY_RESERVE, NFT_TICKETS, PROMO_BUDGET, PURCHASES, RANDOMNESS_FEES,
+    REMOTE_ACCOUNTS, RESERVE, SENDING_IBC_PAYOUTS, SPEND_LEDGER, TICKETS, UNBONDING_RELEASES,
+    USED_VOUCHERS, WINNERS,
+};
 
-pub fn ensure_ticket_is_valid(ticket: &String) -> Result<(), ContractError> {
+pub const BPS_DENOMINATOR: u128 = 10_000;
+
+const IBC_HOOKS_SENDER_PREFIX: &str = "ibc-wasm-hook-intermediary";
+pub const IBC_PAYOUT_REPLY_ID: u64 = 1;
+/// Seconds before a prize sent back over IBC times out
+const IBC_TRANSFER_TIMEOUT: u64 = 600;
+
+const DAY: u64 = 86_400;
+const WEEK: u64 = 7 * DAY;
+/// Seconds before looser gaming limits take effect
+const GAMING_LIMITS_DELAY: u64 = DAY;
+
+/// Checked addition for the `u64` counters, which have no `Uint` counterpart
+pub fn checked_add_u64(a: u64, b: u64) -> Result<u64, OverflowError> {
+    a.checked_add(b)
+        .ok_or_else(|| OverflowError::new(OverflowOperation::Add, a, b))
+}
+
+pub fn ensure_ticket_is_valid(ticket: &str) -> Result<(), ContractError> {
     if ticket.len().ne(&6) {
         return Err(ContractError::InvalidTicket);
     }
@@ -22,57 +62,419 @@ pub fn ensure_ticket_is_valid(ticket: &String) -> Result<(), ContractError> {
     Ok(())
 }
 
-/// Ensure user has sent in enought funds to cover tickets price
-pub fn ensure_is_enough_funds_to_cover_tickets(
+/// Ensure user has sent exactly the tickets price, anything above it would not be owed
+/// to anybody and end up as surplus
+pub fn ensure_exact_funds_for_tickets(
     required_funds: &Coin,
     sent_fund: &[Coin],
 ) -> Result<(), ContractError> {
+    if sent_fund.iter().any(|c| c.denom != required_funds.denom) {
+        return Err(ContractError::InvalidCoin);
+    }
+
+    let sent = sent_fund
+        .iter()
+        .try_fold(Uint128::zero(), |acc, c| acc.checked_add(c.amount))?;
+
+    match sent.cmp(&required_funds.amount) {
+        Ordering::Less => Err(ContractError::InsufficientFunds),
+        Ordering::Greater => Err(ContractError::ExcessFunds),
+        Ordering::Equal => Ok(()),
+    }
+}
+
+/// Returns the amount sent in the given denom, failing if none was sent
+pub fn ensure_funds_in_denom(denom: &str, sent_fund: &[Coin]) -> Result<Uint128, ContractError> {
     let fund = sent_fund
         .iter()
-        .find(|c| c.denom == required_funds.denom)
+        .find(|c| c.denom == denom)
         .ok_or(ContractError::InvalidCoin)?;
 
-    if fund.amount < required_funds.amount {
+    if fund.amount.is_zero() {
         return Err(ContractError::InsufficientFunds);
     }
 
+    Ok(fund.amount)
+}
+
+/// Adds ticket sales to a draw, setting the second-chance and progressive jackpot
+/// shares aside from the pot
+pub fn add_ticket_sales(
+    storage: &mut dyn Storage,
+    config: &Config,
+    draw: &mut Draw,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let pool_share = match &config.second_chance {
+        Some(second_chance) => {
+            amount.checked_multiply_ratio(second_chance.pool_bps, BPS_DENOMINATOR)?
+        }
+        None => Uint128::zero(),
+    };
+
+    let jackpot_share = amount.checked_multiply_ratio(
+        config.progressive_jackpot_bps.unwrap_or_default(),
+        BPS_DENOMINATOR,
+    )?;
+
+    let fee_share = amount.checked_multiply_ratio(
+        config.randomness_fee_bps.unwrap_or_default(),
+        BPS_DENOMINATOR,
+    )?;
+
+    draw.jackpot_contribution = draw.jackpot_contribution.checked_add(jackpot_share)?;
+    draw.randomness_fee_contribution = draw.randomness_fee_contribution.checked_add(fee_share)?;
+
+    if !fee_share.is_zero() {
+        add_randomness_fees(storage, &coin(fee_share.u128(), &draw.total_prize.denom))?;
+    }
+
+    if !jackpot_share.is_zero() {
+        let jackpot = JACKPOT.may_load(storage)?.unwrap_or_default();
+        JACKPOT.save(storage, &jackpot.checked_add(jackpot_share)?)?;
+    }
+
+    draw.second_chance_pool = draw.second_chance_pool.checked_add(pool_share)?;
+    draw.total_prize.amount = draw.total_prize.amount.checked_add(
+        amount
+            .checked_sub(pool_share)?
+            .checked_sub(jackpot_share)?
+            .checked_sub(fee_share)?,
+    )?;
+
+    update_liabilities(storage, |mut liabilities| {
+        liabilities.open_pots = liabilities
+            .open_pots
+            .checked_add(amount.checked_sub(jackpot_share)?.checked_sub(fee_share)?)?;
+        Ok(liabilities)
+    })
+}
+
+pub fn update_liabilities<F>(storage: &mut dyn Storage, action: F) -> Result<(), ContractError>
+where
+    F: FnOnce(Liabilities) -> Result<Liabilities, ContractError>,
+{
+    let liabilities = action(LIABILITIES.may_load(storage)?.unwrap_or_default())?;
+    LIABILITIES.save(storage, &liabilities)?;
+
     Ok(())
 }
 
-pub fn create_next_draw(deps: DepsMut, env: &Env, inital_prize: Uint128) -> StdResult<()> {
-    let id = DRAWS_INDEX.update(deps.storage, |id: u64| -> StdResult<u64> { Ok(id.add(1)) })?;
-    let config = CONFIG.load(deps.storage)?;
+/// Compares the contract balance against everything it owes, funds set aside by the owner included
+/// Withdrawn principal is owed as soon as it is undelegated, so until it is released it
+/// counts as funds the contract holds
+pub fn calculate_solvency(
+    storage: &dyn Storage,
+    balance: Coin,
+    now: Timestamp,
+) -> Result<SolvencyResponse, ContractError> {
+    let liabilities = LIABILITIES.may_load(storage)?.unwrap_or_default();
+    let reserve = RESERVE.may_load(storage)?.unwrap_or_default();
+    let promo_budget = PROMO_BUDGET.may_load(storage)?.unwrap_or_default();
+    let loyalty_reserve = LOYALTY_RESERVE.may_load(storage)?.unwrap_or_default();
+    let jackpot = JACKPOT.may_load(storage)?.unwrap_or_default();
+    let randomness_fees = RANDOMNESS_FEES
+        .may_load(storage, &balance.denom)?
+        .unwrap_or_default();
+    let total_liabilities = liabilities
+        .total()?
+        .checked_add(reserve)?
+        .checked_add(promo_budget)?
+        .checked_add(loyalty_reserve)?
+        .checked_add(jackpot)?
+        .checked_add(randomness_fees)?;
+    let unbonding = UNBONDING_RELEASES
+        .range(
+            storage,
+            Some(Bound::exclusive(now.seconds())),
+            None,
+            Order::Ascending,
+        )
+        .try_fold(Uint128::zero(), |acc, item| -> StdResult<Uint128> {
+            Ok(acc.checked_add(item?.1)?)
+        })?;
+    let assets = balance.amount.checked_add(unbonding)?;
 
-    let end_time = match config.interval {
-        Duration::Height(_) => Expiration::AtHeight(env.block.height).add(config.interval),
-        Duration::Time(_) => Expiration::AtTime(env.block.time).add(config.interval),
-    }?;
+    Ok(SolvencyResponse {
+        surplus: assets.saturating_sub(total_liabilities),
+        deficit: total_liabilities.saturating_sub(assets),
+        balance,
+        liabilities,
+        reserve,
+        promo_budget,
+        loyalty_reserve,
+        jackpot,
+        randomness_fees,
+        unbonding,
+        total_liabilities,
+    })
+}
+
+pub fn create_next_draw(
+    deps: DepsMut,
+    env: &Env,
+    inital_prize: Uint128,
+) -> Result<(), ContractError> {
+    let id = DRAWS_INDEX.update(deps.storage, |id: u64| -> StdResult<u64> {
+        Ok(checked_add_u64(id, 1)?)
+    })?;
+    let config = CONFIG.load(deps.storage)?;
 
-    let prize_per_match = Some(calculate_prize_distribution(
-        inital_prize.clone(),
-        config.percentage_per_match.clone(),
-    ));
+    let end_time = next_draw_end_time(&config, env)?;
 
-    DRAWS.save(
-        deps.storage,
+    let mut draw = Draw::new(
         id,
-        &Draw::new(
-            id,
-            end_time,
-            config.ticket_price,
-            inital_prize,
-            prize_per_match,
-        ),
+        end_time,
+        config.ticket_price.clone(),
+        inital_prize,
+        env.block.time,
+    );
+
+    update_prize_per_match(deps.storage, &config, &mut draw)?;
+
+    DRAWS.save(deps.storage, id, &draw)?;
+
+    Ok(())
+}
+
+/// Recalculates the prize distribution of a draw from its current pot, capping the
+/// jackpot and topping it up from the reserve when it falls below the guaranteed minimum
+pub fn update_prize_per_match(
+    storage: &dyn Storage,
+    config: &Config,
+    draw: &mut Draw,
+) -> Result<(), ContractError> {
+    let mut prize_per_match = calculate_tier_distribution(
+        draw.total_prize.amount,
+        config.tiers(),
+        draw.winners_per_match,
     )?;
 
+    draw.jackpot_overflow = Uint128::zero();
+
+    // A fixed top tier already promises each winner a set amount, capping the
+    // total would shortchange every one of them
+    let jackpot_is_shared = matches!(config.tiers()[5], PrizeTier::Share(_));
+
+    let jackpot_cap = config.jackpot_cap.as_ref().filter(|_| jackpot_is_shared);
+
+    if let Some(cap) = jackpot_cap {
+        if prize_per_match[5] > cap.amount {
+            draw.jackpot_overflow = prize_per_match[5].checked_sub(cap.amount)?;
+            prize_per_match[5] = cap.amount;
+
+            if cap.overflow == OverflowPolicy::NextTier {
+                prize_per_match[4] = prize_per_match[4].checked_add(draw.jackpot_overflow)?;
+            }
+        }
+    }
+
+    let min_jackpot = match (config.min_jackpot, jackpot_cap) {
+        (Some(min_jackpot), Some(cap)) => Some(min_jackpot.min(cap.amount)),
+        (min_jackpot, _) => min_jackpot,
+    };
+
+    let reserve = RESERVE.may_load(storage)?.unwrap_or_default();
+
+    draw.jackpot_subsidy = calculate_jackpot_subsidy(prize_per_match[5], min_jackpot, reserve);
+    prize_per_match[5] = prize_per_match[5].checked_add(draw.jackpot_subsidy)?;
+
+    draw.prize_per_match = Some(prize_per_match);
+
     Ok(())
 }
 
-pub fn calculate_prize_distribution(
+pub fn calculate_jackpot_subsidy(
+    jackpot: Uint128,
+    min_jackpot: Option<Uint128>,
+    reserve: Uint128,
+) -> Uint128 {
+    match min_jackpot {
+        Some(min_jackpot) if jackpot < min_jackpot => (min_jackpot - jackpot).min(reserve),
+        _ => Uint128::zero(),
+    }
+}
+
+/// Splits the pot between tiers. Once winners are known, fixed tiers are paid first
+/// and the shared tiers split what is left; when the pot can't cover every fixed
+/// winner, the pot is shared pro-rata between fixed tiers and shared tiers get nothing.
+/// Before that, fixed tiers show the amount a single winner would get.
+pub fn calculate_tier_distribution(
     total_amount: Uint128,
-    percent_per_matches: [u8; 6],
-) -> [Uint128; 6] {
-    percent_per_matches.map(|p| total_amount.multiply_ratio(p, Uint128::from(100u128)))
+    tiers: [PrizeTier; 6],
+    winners_per_match: Option<[u64; 6]>,
+) -> Result<[Uint128; 6], ContractError> {
+    let mut prize_per_match = [Uint128::zero(); 6];
+
+    let winners_per_match = match winners_per_match {
+        Some(winners_per_match) => winners_per_match,
+        None => {
+            for (prize, tier) in prize_per_match.iter_mut().zip(tiers) {
+                *prize = match tier {
+                    PrizeTier::Fixed(amount) => amount,
+                    PrizeTier::Share(bps) => {
+                        total_amount.checked_multiply_ratio(bps, BPS_DENOMINATOR)?
+                    }
+                };
+            }
+            return Ok(prize_per_match);
+        }
+    };
+
+    let fixed_needed = calculate_fixed_prizes_needed(tiers, winners_per_match)?;
+
+    if fixed_needed > total_amount {
+        for (i, tier) in tiers.iter().enumerate() {
+            if let PrizeTier::Fixed(amount) = tier {
+                let needed = amount.checked_mul(winners_per_match[i].into())?;
+                prize_per_match[i] = total_amount.checked_multiply_ratio(needed, fixed_needed)?;
+            }
+        }
+        return Ok(prize_per_match);
+    }
+
+    let remaining = total_amount.checked_sub(fixed_needed)?;
+
+    for (i, tier) in tiers.iter().enumerate() {
+        prize_per_match[i] = match tier {
+            PrizeTier::Fixed(amount) => amount.checked_mul(winners_per_match[i].into())?,
+            PrizeTier::Share(bps) => remaining.checked_multiply_ratio(*bps, BPS_DENOMINATOR)?,
+        };
+    }
+
+    Ok(prize_per_match)
+}
+
+pub fn calculate_fixed_prizes_needed(
+    tiers: [PrizeTier; 6],
+    winners_per_match: [u64; 6],
+) -> Result<Uint128, ContractError> {
+    tiers
+        .iter()
+        .zip(winners_per_match)
+        .try_fold(Uint128::zero(), |acc, (tier, winners)| match tier {
+            PrizeTier::Fixed(amount) => Ok(acc.checked_add(amount.checked_mul(winners.into())?)?),
+            PrizeTier::Share(_) => Ok(acc),
+        })
+}
+
+pub fn ensure_prize_tiers_are_valid(tiers: &[PrizeTier; 6]) -> Result<(), ContractError> {
+    let total_bps = tiers.iter().fold(0u32, |acc, tier| match tier {
+        PrizeTier::Share(bps) => acc + u32::from(*bps),
+        PrizeTier::Fixed(_) => acc,
+    });
+
+    if total_bps > BPS_DENOMINATOR as u32 {
+        return Err(ContractError::InvalidPrizeTiers);
+    }
+
+    Ok(())
+}
+
+/// Shared tiers in whole percents, rounded down, for `Config::percentage_per_match`
+pub fn shared_percentages(tiers: &[PrizeTier; 6]) -> [u8; 6] {
+    tiers.map(|tier| match tier {
+        PrizeTier::Share(bps) => (bps / 100) as u8,
+        PrizeTier::Fixed(_) => 0,
+    })
+}
+
+pub fn ensure_loyalty_is_valid(loyalty: &LoyaltyConfig) -> Result<(), ContractError> {
+    if loyalty.points_per_free_ticket == 0 {
+        return Err(ContractError::InvalidLoyaltyConfig);
+    }
+
+    Ok(())
+}
+
+pub fn ensure_second_chance_is_valid(
+    second_chance: &SecondChanceConfig,
+) -> Result<(), ContractError> {
+    ensure_bps_is_valid(second_chance.pool_bps)?;
+
+    if second_chance.prize.is_some_and(|prize| prize.is_zero()) {
+        return Err(ContractError::InvalidSecondChance);
+    }
+
+    Ok(())
+}
+
+pub fn ensure_bps_is_valid(bps: u16) -> Result<(), ContractError> {
+    if u128::from(bps) > BPS_DENOMINATOR {
+        return Err(ContractError::InvalidBps);
+    }
+
+    Ok(())
+}
+
+/// Ensures the shares set aside from every sale leave something for the pot
+pub fn ensure_sales_shares_are_valid(config: &Config) -> Result<(), ContractError> {
+    let pool_bps = config
+        .second_chance
+        .as_ref()
+        .map_or(0, |second_chance| second_chance.pool_bps);
+
+    let total_bps = u128::from(pool_bps)
+        + u128::from(config.progressive_jackpot_bps.unwrap_or_default())
+        + u128::from(config.randomness_fee_bps.unwrap_or_default());
+
+    if total_bps > BPS_DENOMINATOR {
+        return Err(ContractError::InvalidBps);
+    }
+
+    Ok(())
+}
+
+/// Adds the jackpot overflow to the shared lower tiers that have winners, weighted by
+/// their share, and returns how much was handed out
+pub fn distribute_overflow(
+    prize_per_match: &mut [Uint128; 6],
+    winners_per_match: [u64; 6],
+    overflow: Uint128,
+    tiers: [PrizeTier; 6],
+) -> Result<Uint128, ContractError> {
+    let weights = tiers.map(|tier| match tier {
+        PrizeTier::Share(bps) => u128::from(bps),
+        PrizeTier::Fixed(_) => 0,
+    });
+
+    let total_weight: u128 = (0..5)
+        .filter(|i| winners_per_match[*i] > 0)
+        .map(|i| weights[i])
+        .sum();
+
+    if total_weight == 0 {
+        return Ok(Uint128::zero());
+    }
+
+    (0..5)
+        .filter(|i| winners_per_match[*i] > 0)
+        .try_fold(Uint128::zero(), |acc, i| {
+            let share = overflow.checked_multiply_ratio(weights[i], total_weight)?;
+            prize_per_match[i] = prize_per_match[i].checked_add(share)?;
+            Ok(acc.checked_add(share)?)
+        })
+}
+
+/// Total amount that will be claimed by the winners, given that each one of them
+/// receives the truncated share of its tier
+pub fn calculate_payouts(
+    prize_per_match: [Uint128; 6],
+    winners_per_match: [u64; 6],
+) -> Result<Uint128, ContractError> {
+    prize_per_match.iter().zip(winners_per_match).try_fold(
+        Uint128::zero(),
+        |acc, (prize, winners)| -> Result<Uint128, ContractError> {
+            if winners == 0 {
+                return Ok(acc);
+            }
+
+            let winners = Uint128::from(winners);
+            let per_winner = prize.checked_div(winners)?;
+
+            Ok(acc.checked_add(per_winner.checked_mul(winners)?)?)
+        },
+    )
 }
 
 pub fn calculate_tickets_prize(
@@ -80,38 +482,608 @@ pub fn calculate_tickets_prize(
     prize_per_match: [Uint128; 6],
     winners_per_match: [u64; 6],
     denom: String,
-) -> Coin {
-    let prize = tickets.iter().fold(Uint128::zero(), |mut acc, t| {
-        if t.matches > 0 {
+) -> Result<Coin, ContractError> {
+    let prize = tickets
+        .iter()
+        .try_fold(Uint128::zero(), |acc, t| -> Result<_, ContractError> {
+            if t.matches == 0 {
+                return Ok(acc);
+            }
+
             let index = t.matches as usize - 1;
-            let ticket_prize = prize_per_match[index]
-                .checked_div(winners_per_match[index].into())
-                .expect("error calculating ticket prize");
-            acc = acc
-                .checked_add(ticket_prize)
-                .expect("error calculating ticket prize")
-        }
-        acc
-    });
+            let ticket_prize =
+                prize_per_match[index].checked_div(winners_per_match[index].into())?;
 
-    coin(prize.u128(), denom)
+            Ok(acc.checked_add(ticket_prize)?)
+        })?;
+
+    Ok(coin(prize.u128(), denom))
 }
 
-pub fn calculate_winner_per_match(
-    tickets: Vec<(Addr, Vec<String>)>,
+pub fn calculate_winner_per_match<T>(
+    tickets: Vec<(T, Vec<String>)>,
     winning_ticket: String,
-) -> [u64; 6] {
-    tickets
+) -> Result<[u64; 6], ContractError> {
+    let mut winners_per_match = [0u64; 6];
+
+    for t in tickets.iter().flat_map(|(_, utickets)| utickets) {
+        let matches = calculate_matches(t, &winning_ticket);
+        if matches > 0 {
+            let index = matches as usize - 1;
+            winners_per_match[index] = checked_add_u64(winners_per_match[index], 1)?;
+        }
+    }
+
+    Ok(winners_per_match)
+}
+
+/// Tickets of a draw along with who they pay out to
+pub type HolderTickets = (TicketHolder, Vec<String>);
+
+/// Lists every ticket of a draw, both those held in wallets and those minted as NFTs
+pub fn draw_tickets(storage: &dyn Storage, draw_id: u64) -> StdResult<Vec<HolderTickets>> {
+    let wallets = TICKETS
+        .prefix(draw_id)
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(addr, tickets)| (TicketHolder::Wallet(addr), tickets)));
+
+    let nfts = NFT_TICKETS
+        .prefix(draw_id)
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(token_id, tickets)| (TicketHolder::Nft(token_id), tickets)));
+
+    wallets.chain(nfts).collect()
+}
+
+/// Lists the tickets of up to `limit` holders of a draw after `after`, wallets first and
+/// then tokens, along with whether there are none left
+pub fn draw_tickets_page(
+    storage: &dyn Storage,
+    draw_id: u64,
+    after: Option<&TicketHolder>,
+    limit: usize,
+) -> StdResult<(Vec<HolderTickets>, bool)> {
+    let (wallets_start, nfts_start, wallets_done) = match after {
+        None => (None, None, false),
+        Some(TicketHolder::Wallet(addr)) => (Some(Bound::exclusive(addr.clone())), None, false),
+        Some(TicketHolder::Nft(token_id)) => {
+            (None, Some(Bound::exclusive(token_id.as_str())), true)
+        }
+    };
+
+    let wallets = TICKETS
+        .prefix(draw_id)
+        .range(storage, wallets_start, None, Order::Ascending)
+        .take(if wallets_done { 0 } else { usize::MAX })
+        .map(|item| item.map(|(addr, tickets)| (TicketHolder::Wallet(addr), tickets)));
+
+    let nfts = NFT_TICKETS
+        .prefix(draw_id)
+        .range(storage, nfts_start, None, Order::Ascending)
+        .map(|item| item.map(|(token_id, tickets)| (TicketHolder::Nft(token_id), tickets)));
+
+    let mut page = wallets
+        .chain(nfts)
+        .take(limit.saturating_add(1))
+        .collect::<StdResult<Vec<_>>>()?;
+    let done = page.len() <= limit;
+    page.truncate(limit);
+
+    Ok((page, done))
+}
+
+/// Ranks a ticket for the second chance, the lowest ranks win. Each ticket is ranked on
+/// its own so that the holders can be gone through in any number of pages
+pub fn second_chance_rank(
+    randomness: &HexBinary,
+    holder: &TicketHolder,
+    index: usize,
+    ticket: &str,
+) -> HexBinary {
+    let holder = match holder {
+        TicketHolder::Wallet(addr) => format!("wallet/{}", addr),
+        TicketHolder::Nft(token_id) => format!("nft/{}", token_id),
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(randomness.as_slice());
+    hasher.update(holder.as_bytes());
+    hasher.update((index as u64).to_be_bytes());
+    hasher.update(ticket.as_bytes());
+
+    HexBinary::from(hasher.finalize().as_slice())
+}
+
+pub fn query_nft_owner(
+    querier: &QuerierWrapper,
+    ticket_nft: &Addr,
+    token_id: &str,
+) -> StdResult<String> {
+    let response: OwnerOfResponse = querier.query_wasm_smart(
+        ticket_nft,
+        &Cw721QueryMsg::OwnerOf {
+            token_id: token_id.to_string(),
+            include_expired: None,
+        },
+    )?;
+
+    Ok(response.owner)
+}
+
+/// Address a ticket pays out to, if the token holding it still has a known owner
+pub fn ticket_holder_addr(deps: Deps, config: &Config, holder: TicketHolder) -> Option<Addr> {
+    match holder {
+        TicketHolder::Wallet(addr) => Some(addr),
+        TicketHolder::Nft(token_id) => config.ticket_nft.as_ref().and_then(|nft| {
+            query_nft_owner(&deps.querier, nft, &token_id)
+                .and_then(|owner| deps.api.addr_validate(&owner))
+                .ok()
+        }),
+    }
+}
+
+/// Address ibc-hooks executes the memo as on behalf of `remote_sender`, the hash of
+/// `channel/remote_sender` with the hooks prefix, encoded with the local bech32 prefix
+pub fn derive_intermediate_sender(
+    channel: &str,
+    remote_sender: &str,
+    bech32_prefix: &str,
+) -> Result<String, ContractError> {
+    let prefix_hash = Sha256::digest(IBC_HOOKS_SENDER_PREFIX.as_bytes());
+    let hash = Sha256::new()
+        .chain_update(prefix_hash)
+        .chain_update(format!("{}/{}", channel, remote_sender).as_bytes())
+        .finalize();
+
+    bech32::encode(bech32_prefix, hash.to_base32(), Variant::Bech32)
+        .map_err(|_| ContractError::InvalidRemoteSender)
+}
+
+pub fn ensure_remote_sender_is_valid(
+    sender: &Addr,
+    channel: &str,
+    remote_sender: &str,
+) -> Result<(), ContractError> {
+    let (prefix, _, _) =
+        bech32::decode(sender.as_str()).map_err(|_| ContractError::InvalidRemoteSender)?;
+
+    if bech32::decode(remote_sender).is_err()
+        || derive_intermediate_sender(channel, remote_sender, &prefix)? != sender.as_str()
+    {
+        return Err(ContractError::InvalidRemoteSender);
+    }
+
+    Ok(())
+}
+
+pub fn ensure_tickets_are_available(
+    config: &Config,
+    draw: &Draw,
+    n_tickets: u32,
+) -> Result<(), ContractError> {
+    if config
+        .max_tickets_per_draw
+        .is_some_and(|max| draw.total_tickets.checked_add(u64::from(n_tickets)) > Some(max))
+    {
+        return Err(ContractError::DrawSoldOut);
+    }
+
+    Ok(())
+}
+
+pub fn is_sold_out(config: &Config, draw: &Draw) -> bool {
+    config
+        .max_tickets_per_draw
+        .is_some_and(|max| draw.total_tickets >= max)
+}
+
+pub fn ensure_allowlist_is_valid(
+    api: &dyn Api,
+    allowlist: Allowlist,
+) -> Result<Allowlist, ContractError> {
+    Ok(match allowlist {
+        Allowlist::Group { contract } => Allowlist::Group {
+            contract: api.addr_validate(contract.as_str())?,
+        },
+        allowlist => allowlist,
+    })
+}
+
+/// Denied addresses are always refused, others must pass the allowlist when enabled
+pub fn ensure_address_is_allowed(
+    deps: Deps,
+    config: &Config,
+    addr: &Addr,
+) -> Result<(), ContractError> {
+    if DENYLIST.has(deps.storage, addr.clone()) {
+        return Err(ContractError::AddressNotAllowed);
+    }
+
+    let allowed = match &config.allowlist {
+        Allowlist::Disabled => true,
+        Allowlist::Static => ALLOWLIST.has(deps.storage, addr.clone()),
+        Allowlist::Group { contract } => {
+            let member: MemberResponse = deps.querier.query_wasm_smart(
+                contract,
+                &Cw4QueryMsg::Member {
+                    addr: addr.to_string(),
+                    at_height: None,
+                },
+            )?;
+            member.weight.is_some()
+        }
+    };
+
+    if !allowed {
+        return Err(ContractError::AddressNotAllowed);
+    }
+
+    Ok(())
+}
+
+pub fn spent_since(ledger: &[Spend], since: Timestamp) -> StdResult<Uint128> {
+    ledger
         .iter()
-        .fold([0, 0, 0, 0, 0, 0], |mut acc, (_, utickets)| {
-            utickets.iter().for_each(|t| {
-                let matches = calculate_matches(t, &winning_ticket);
-                if matches > 0 {
-                    acc[matches as usize - 1] += 1;
-                }
-            });
-            acc
+        .filter(|spend| spend.at > since)
+        .try_fold(Uint128::zero(), |acc, spend| {
+            Ok(acc.checked_add(spend.amount)?)
+        })
+}
+
+/// Spending over the last day and the last week
+pub fn recent_spend(ledger: &[Spend], now: Timestamp) -> StdResult<(Uint128, Uint128)> {
+    Ok((
+        spent_since(ledger, now.minus_seconds(DAY))?,
+        spent_since(ledger, now.minus_seconds(WEEK))?,
+    ))
+}
+
+/// When the player's own limits let them buy again, if they are held back right now
+pub fn next_purchase_at(
+    limits: &GamingLimits,
+    ledger: &[Spend],
+    now: Timestamp,
+) -> Option<Timestamp> {
+    let excluded_until = limits.excluded_until.filter(|until| *until > now);
+    let cooldown_ends = limits
+        .cooldown
+        .zip(ledger.last())
+        .map(|(cooldown, last)| last.at.plus_seconds(cooldown))
+        .filter(|ends| *ends > now);
+
+    excluded_until.max(cooldown_ends)
+}
+
+/// Rebuilds the ledger of a contract deployed before it existed: the current pot and
+/// whatever the winners of raffled draws have not claimed yet
+pub fn rebuild_liabilities(storage: &dyn Storage) -> Result<Liabilities, ContractError> {
+    let current_draw = DRAWS.load(storage, DRAWS_INDEX.load(storage)?)?;
+
+    let mut liabilities = Liabilities {
+        open_pots: current_draw.total_prize.amount,
+        ..Liabilities::default()
+    };
+
+    let draws = DRAWS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    for (draw_id, draw) in draws {
+        if draw.status != Status::Claimable {
+            continue;
+        }
+
+        let prize_per_match = draw.prize_per_match.unwrap_or_default();
+        let winners_per_match = draw.winners_per_match.unwrap_or_default();
+        let winner_number = draw.winner_number.unwrap_or_default();
+
+        let mut unclaimed = calculate_payouts(prize_per_match, winners_per_match)?;
+
+        let claimed = WINNERS
+            .prefix(draw_id)
+            .keys(storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        for winner in claimed {
+            let tickets = TICKETS
+                .may_load(storage, (draw_id, winner))?
+                .unwrap_or_default();
+            let prize = calculate_tickets_prize(
+                check_tickets(tickets, winner_number.clone()),
+                prize_per_match,
+                winners_per_match,
+                draw.ticket_price.denom.clone(),
+            )?;
+            unclaimed = unclaimed.checked_sub(prize.amount)?;
+        }
+
+        liabilities.unclaimed_prizes = liabilities.unclaimed_prizes.checked_add(unclaimed)?;
+    }
+
+    Ok(liabilities)
+}
+
+/// Checks that `buyer` may add these tickets to an open draw, returning the draw and
+/// the number of tickets
+pub fn ensure_tickets_can_be_bought(
+    deps: Deps,
+    env: &Env,
+    config: &Config,
+    buyer: &Addr,
+    draw_id: u64,
+    tickets: &[String],
+) -> Result<(Draw, u32), ContractError> {
+    if config.paused {
+        return Err(ContractError::ContractPaused);
+    }
+
+    if config.no_loss.is_some() {
+        return Err(ContractError::InvalidGameMode);
+    }
+
+    ensure_address_is_allowed(deps, config, buyer)?;
+
+    if tickets.is_empty() {
+        return Err(ContractError::NoTickets);
+    }
+
+    let draw = DRAWS.load(deps.storage, draw_id)?;
+
+    if draw.status != Status::Open || draw.end_time.is_expired(&env.block) {
+        return Err(ContractError::DrawIsNotOpen);
+    }
+
+    let tickets_held = TICKETS
+        .may_load(deps.storage, (draw_id, buyer.clone()))?
+        .unwrap_or_default()
+        .len();
+
+    // Tickets minted or moved away still count against whoever bought them
+    let tickets_bought = PURCHASES
+        .may_load(deps.storage, (draw_id, buyer.clone()))?
+        .unwrap_or_default()
+        .iter()
+        .map(|purchase| purchase.tickets as usize)
+        .sum::<usize>();
+    let tickets_held = tickets_held.max(tickets_bought);
+
+    let n_tickets =
+        u32::try_from(tickets.len()).map_err(|_| ContractError::MaxTicketsPerUserExceeded)?;
+
+    if tickets.len().checked_add(tickets_held) > Some(config.max_tickets_per_user as usize) {
+        return Err(ContractError::MaxTicketsPerUserExceeded);
+    }
+
+    ensure_tickets_are_available(config, &draw, n_tickets)?;
+
+    tickets.iter().try_for_each(|t| ensure_ticket_is_valid(t))?;
+
+    Ok((draw, n_tickets))
+}
+
+/// Hands bought or redeemed tickets to `buyer` and adds what they are worth to the
+/// draw, returning every ticket the buyer now holds in it
+pub fn record_tickets(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &Config,
+    buyer: &Addr,
+    draw: &mut Draw,
+    tickets: Vec<String>,
+    purchase: Purchase,
+) -> Result<Vec<String>, ContractError> {
+    record_spend(storage, buyer, env.block.time, purchase.paid.amount)?;
+
+    // Whatever was not paid by the buyer was subsidised, the pot gets the full price
+    let sales = purchase.paid.amount.checked_add(purchase.subsidy)?;
+
+    draw.total_tickets = checked_add_u64(draw.total_tickets, u64::from(purchase.tickets))?;
+
+    let mut purchases = PURCHASES
+        .may_load(storage, (draw.id, buyer.clone()))?
+        .unwrap_or_default();
+    purchases.push(purchase);
+    PURCHASES.save(storage, (draw.id, buyer.clone()), &purchases)?;
+
+    let mut tickets_bought = TICKETS
+        .may_load(storage, (draw.id, buyer.clone()))?
+        .unwrap_or_default();
+    tickets_bought.extend(tickets);
+    TICKETS.save(storage, (draw.id, buyer.clone()), &tickets_bought)?;
+
+    add_ticket_sales(storage, config, draw, sales)?;
+    update_prize_per_match(storage, config, draw)?;
+
+    DRAWS.save(storage, draw.id, draw)?;
+
+    Ok(tickets_bought)
+}
+
+/// Gaming limits in effect at `now`, once the delay of looser pending limits is over
+pub fn gaming_limits_at(mut limits: GamingLimits, now: Timestamp) -> GamingLimits {
+    match limits.pending.take() {
+        Some(pending) if pending.activates_at <= now => {
+            limits.daily_limit = pending.daily_limit;
+            limits.weekly_limit = pending.weekly_limit;
+            limits.cooldown = pending.cooldown;
+        }
+        pending => limits.pending = pending,
+    }
+
+    limits
+}
+
+/// Sets new gaming limits. Tighter ones apply right away, while looser ones only apply
+/// after `GAMING_LIMITS_DELAY` so that they can't be lifted on an impulse. A new request
+/// replaces whatever was still pending
+pub fn change_gaming_limits(
+    limits: GamingLimits,
+    now: Timestamp,
+    daily_limit: Option<Uint128>,
+    weekly_limit: Option<Uint128>,
+    cooldown: Option<u64>,
+) -> GamingLimits {
+    let mut limits = gaming_limits_at(limits, now);
+
+    let is_tighter_limit = |new: Option<Uint128>, current: Option<Uint128>| {
+        current.map_or(true, |current| new.is_some_and(|new| new <= current))
+    };
+    let daily_is_tighter = is_tighter_limit(daily_limit, limits.daily_limit);
+    let weekly_is_tighter = is_tighter_limit(weekly_limit, limits.weekly_limit);
+    let cooldown_is_tighter = limits
+        .cooldown
+        .map_or(true, |current| cooldown.is_some_and(|new| new >= current));
+
+    if daily_is_tighter {
+        limits.daily_limit = daily_limit;
+    }
+    if weekly_is_tighter {
+        limits.weekly_limit = weekly_limit;
+    }
+    if cooldown_is_tighter {
+        limits.cooldown = cooldown;
+    }
+
+    limits.pending = if daily_is_tighter && weekly_is_tighter && cooldown_is_tighter {
+        None
+    } else {
+        Some(PendingGamingLimits {
+            daily_limit,
+            weekly_limit,
+            cooldown,
+            activates_at: now.plus_seconds(GAMING_LIMITS_DELAY),
         })
+    };
+
+    limits
+}
+
+/// Checks a purchase against the player's self-set limits and adds it to their ledger
+pub fn record_spend(
+    storage: &mut dyn Storage,
+    addr: &Addr,
+    now: Timestamp,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let limits = GAMING_LIMITS
+        .may_load(storage, addr.clone())?
+        .unwrap_or_default();
+    let limits = gaming_limits_at(limits, now);
+
+    if limits.excluded_until.is_some_and(|until| until > now) {
+        return Err(ContractError::SelfExcluded);
+    }
+
+    let mut ledger = SPEND_LEDGER
+        .may_load(storage, addr.clone())?
+        .unwrap_or_default();
+
+    // The cooldown may outlast the week the ledger keeps, so it goes first
+    if next_purchase_at(&limits, &ledger, now).is_some() {
+        return Err(ContractError::PurchaseCooldown);
+    }
+
+    ledger.retain(|spend| spend.at > now.minus_seconds(WEEK));
+
+    let (spent_last_day, spent_last_week) = recent_spend(&ledger, now)?;
+    let spent_last_day = spent_last_day.checked_add(amount)?;
+    let spent_last_week = spent_last_week.checked_add(amount)?;
+
+    if limits
+        .daily_limit
+        .is_some_and(|limit| spent_last_day > limit)
+        || limits
+            .weekly_limit
+            .is_some_and(|limit| spent_last_week > limit)
+    {
+        return Err(ContractError::SpendingLimitExceeded);
+    }
+
+    ledger.push(Spend { at: now, amount });
+    SPEND_LEDGER.save(storage, addr.clone(), &ledger)?;
+
+    Ok(())
+}
+
+/// Pays a prize, sending it back over IBC when the winner bought through ibc-hooks. The
+/// transfer asks ibc-hooks for a callback, its packet sequence is then kept from the reply
+/// so that a failed transfer can be credited back to the winner
+pub fn prize_payout_msg(
+    storage: &mut dyn Storage,
+    env: &Env,
+    winner: &Addr,
+    prize: Coin,
+) -> StdResult<SubMsg> {
+    Ok(match REMOTE_ACCOUNTS.may_load(storage, winner.clone())? {
+        Some(remote) => {
+            let transfer = transfer_msg(
+                &remote.channel,
+                env.contract.address.as_str(),
+                &remote.sender,
+                &prize,
+                env.block.time.plus_seconds(IBC_TRANSFER_TIMEOUT),
+                &format!(r#"{{"ibc_callback":"{}"}}"#, env.contract.address),
+            );
+
+            let mut sending = SENDING_IBC_PAYOUTS.may_load(storage)?.unwrap_or_default();
+            sending.push(IbcPayout {
+                channel: remote.channel,
+                winner: winner.clone(),
+                amount: prize,
+            });
+            SENDING_IBC_PAYOUTS.save(storage, &sending)?;
+
+            SubMsg::reply_on_success(transfer, IBC_PAYOUT_REPLY_ID)
+        }
+        None => SubMsg::new(BankMsg::Send {
+            to_address: winner.to_string(),
+            amount: vec![prize],
+        }),
+    })
+}
+
+/// Tickets can only move while the draw is open, or once it is claimable as long as
+/// none of the given addresses has claimed its prize yet
+pub fn ensure_tickets_can_move(
+    storage: &dyn Storage,
+    env: &Env,
+    draw: &Draw,
+    addrs: &[&Addr],
+) -> Result<(), ContractError> {
+    match draw.status {
+        Status::Open if !draw.end_time.is_expired(&env.block) => Ok(()),
+        Status::Claimable => {
+            // Once the second-chance randomness is out, moving tickets would change their
+            // rank or have the raffle count them twice
+            if !matches!(
+                draw.second_chance_status,
+                None | Some(SecondChanceStatus::Settled)
+            ) {
+                return Err(ContractError::SecondChanceNotSettled);
+            }
+
+            for addr in addrs {
+                if WINNERS.has(storage, (draw.id, (*addr).clone())) {
+                    return Err(ContractError::PrizeAlreadyClaimed);
+                }
+            }
+            Ok(())
+        }
+        _ => Err(ContractError::DrawIsNotOpen),
+    }
+}
+
+/// Removes one occurrence of every ticket from `owned`
+pub fn take_tickets(owned: &mut Vec<String>, tickets: &[String]) -> Result<(), ContractError> {
+    for ticket in tickets {
+        let position = owned
+            .iter()
+            .position(|t| t == ticket)
+            .ok_or(ContractError::TicketNotOwned)?;
+        owned.swap_remove(position);
+    }
+
+    Ok(())
 }
 
 pub fn calculate_matches(winning_ticket: &str, ticket: &str) -> u8 {
@@ -141,6 +1113,537 @@ pub fn check_tickets(tickets: Vec<String>, winning_ticket: String) -> Vec<Ticket
         .collect()
 }
 
+/// Checks the voucher was signed by the voucher key, is still valid and was never used
+pub fn ensure_voucher_is_valid(
+    deps: Deps,
+    env: &Env,
+    config: &Config,
+    buyer: &Addr,
+    voucher: &Voucher,
+) -> Result<(), ContractError> {
+    let pubkey = config
+        .voucher_pubkey
+        .as_ref()
+        .ok_or(ContractError::InvalidVoucher)?;
+
+    if voucher.expires_at <= env.block.time {
+        return Err(ContractError::VoucherExpired);
+    }
+
+    if voucher
+        .recipient
+        .as_ref()
+        .is_some_and(|recipient| recipient != buyer.as_str())
+    {
+        return Err(ContractError::InvalidVoucher);
+    }
+
+    if u128::from(voucher.discount_bps) > BPS_DENOMINATOR {
+        return Err(ContractError::InvalidVoucher);
+    }
+
+    if USED_VOUCHERS.has(deps.storage, &voucher.nonce) {
+        return Err(ContractError::VoucherAlreadyUsed);
+    }
+
+    let payload = to_vec(&VoucherPayload {
+        contract: env.contract.address.to_string(),
+        nonce: voucher.nonce.clone(),
+        discount_bps: voucher.discount_bps,
+        max_subsidy: voucher.max_subsidy,
+        expires_at: voucher.expires_at,
+        recipient: voucher.recipient.clone(),
+    })?;
+    let hash = Sha256::digest(payload);
+
+    let verified = deps
+        .api
+        .secp256k1_verify(&hash, &voucher.signature, pubkey)
+        .map_err(|_| ContractError::InvalidVoucher)?;
+
+    if !verified {
+        return Err(ContractError::InvalidVoucher);
+    }
+
+    Ok(())
+}
+
+/// Brings the deposit weight up to `now`, clamped to the time the draw was open.
+/// Weight accumulated during a previous draw is discarded, as that draw was already raffled
+pub fn accrue_deposit_weight(
+    deposit: &Deposit,
+    draw: &Draw,
+    now: Timestamp,
+) -> Result<Deposit, ContractError> {
+    let end = match draw.end_time {
+        Expiration::AtTime(end) => end,
+        _ => return Err(ContractError::InvalidGameMode),
+    };
+
+    let to = now.min(end).seconds();
+    let start = draw.opened_at.seconds();
+
+    let (weight, from) = if deposit.draw_id == draw.id {
+        (deposit.weight, deposit.last_update.seconds().max(start))
+    } else {
+        (Uint128::zero(), start)
+    };
+
+    let held = Uint128::from(to.saturating_sub(from));
+
+    Ok(Deposit {
+        principal: deposit.principal,
+        weight: weight.checked_add(deposit.principal.checked_mul(held)?)?,
+        last_update: now,
+        draw_id: draw.id,
+    })
+}
+
+/// Whether a depositor takes the pick over from those before it, once its weight brought
+/// theirs up to `total`. Going through all of them that way picks each with a probability
+/// proportional to its weight, however many pages it takes
+pub fn takes_weighted_pick(
+    randomness: &[u8; 32],
+    depositor: &Addr,
+    weight: Uint128,
+    total: Uint128,
+) -> bool {
+    let seed: [u8; 32] = Sha256::new()
+        .chain_update(randomness)
+        .chain_update(depositor.as_bytes())
+        .finalize()
+        .into();
+
+    Uint128::from(int_in_range(seed, 0, total.u128() - 1)) < weight
+}
+
+/// A no-loss config stakes the ticket denom, so it must be the one the chain bonds
+pub fn ensure_no_loss_denom_is_bonded(
+    querier: &QuerierWrapper,
+    config: &Config,
+) -> Result<(), ContractError> {
+    if config.no_loss.is_some() && querier.query_bonded_denom()? != config.ticket_price.denom {
+        return Err(ContractError::InvalidCoin);
+    }
+
+    Ok(())
+}
+
+/// Staking rewards of a validator in the given denom, which the chain pays out
+/// automatically whenever the delegation changes
+pub fn query_pending_rewards(
+    querier: &QuerierWrapper,
+    delegator: &Addr,
+    validator: &str,
+    denom: &str,
+) -> StdResult<Uint128> {
+    Ok(querier
+        .query_delegation(delegator, validator)?
+        .and_then(|d| d.accumulated_rewards.into_iter().find(|c| c.denom == denom))
+        .map(|c| c.amount)
+        .unwrap_or_default())
+}
+
+/// Moves rewards paid out to the contract into the pot of the current draw
+pub fn add_rewards_to_pot(
+    storage: &mut dyn Storage,
+    draw: &mut Draw,
+    rewards: Uint128,
+) -> Result<(), ContractError> {
+    if rewards.is_zero() {
+        return Ok(());
+    }
+
+    draw.total_prize.amount = draw.total_prize.amount.checked_add(rewards)?;
+
+    update_liabilities(storage, |mut liabilities| {
+        liabilities.open_pots = liabilities.open_pots.checked_add(rewards)?;
+        Ok(liabilities)
+    })
+}
+
+pub fn apply_config_changes(
+    api: &dyn Api,
+    config: &mut Config,
+    changes: UpdateConfigMsg,
+) -> Result<(), ContractError> {
+    if let Some(new_interval) = changes.interval {
+        config.interval = new_interval;
+    }
+
+    if let Some(new_nois_proxy) = changes.nois_proxy {
+        config.nois_proxy = api.addr_validate(new_nois_proxy.as_str())?;
+    }
+
+    if let Some(request_timeout) = changes.request_timeout {
+        config.request_timeout = request_timeout;
+    }
+
+    if let Some(new_treasury_fee) = changes.treasury_fee {
+        config.treasury_fee = new_treasury_fee;
+    }
+
+    if changes.percentage_per_match.is_some() && changes.prize_tiers.is_some() {
+        return Err(ContractError::InvalidPrizeTiers);
+    }
+
+    // New percentages replace the prize tiers altogether
+    if let Some(new_percentage_per_match) = changes.percentage_per_match {
+        config.percentage_per_match = new_percentage_per_match;
+        config.prize_tiers = None;
+    }
+
+    if let Some(new_ticket_price) = changes.ticket_price {
+        config.ticket_price = new_ticket_price;
+    }
+
+    if let Some(new_max_tickets_per_user) = changes.max_tickets_per_user {
+        config.max_tickets_per_user = new_max_tickets_per_user;
+    }
+
+    if let Some(new_min_jackpot) = changes.min_jackpot {
+        config.min_jackpot = Some(new_min_jackpot);
+    }
+
+    if let Some(new_jackpot_cap) = changes.jackpot_cap {
+        config.jackpot_cap = Some(new_jackpot_cap);
+    }
+
+    if changes.clear_min_jackpot {
+        config.min_jackpot = None;
+    }
+
+    if changes.clear_jackpot_cap {
+        config.jackpot_cap = None;
+    }
+
+    if let Some(new_prize_tiers) = changes.prize_tiers {
+        config.percentage_per_match = shared_percentages(&new_prize_tiers);
+        config.prize_tiers = Some(new_prize_tiers);
+    }
+
+    if let Some(new_config_timelock) = changes.config_timelock {
+        config.config_timelock = Some(new_config_timelock);
+    }
+
+    if let Some(paused) = changes.paused {
+        config.paused = paused;
+    }
+
+    if let Some(new_referral_bps) = changes.referral_bps {
+        ensure_bps_is_valid(new_referral_bps)?;
+        config.referral_bps = Some(new_referral_bps);
+    }
+
+    if let Some(new_voucher_pubkey) = changes.voucher_pubkey {
+        config.voucher_pubkey = Some(new_voucher_pubkey);
+    }
+
+    if let Some(new_loyalty) = changes.loyalty {
+        ensure_loyalty_is_valid(&new_loyalty)?;
+        config.loyalty = Some(new_loyalty);
+    }
+
+    if let Some(new_second_chance) = changes.second_chance {
+        ensure_second_chance_is_valid(&new_second_chance)?;
+        config.second_chance = Some(new_second_chance);
+    }
+
+    if let Some(new_progressive_jackpot_bps) = changes.progressive_jackpot_bps {
+        ensure_bps_is_valid(new_progressive_jackpot_bps)?;
+        config.progressive_jackpot_bps = Some(new_progressive_jackpot_bps);
+    }
+
+    if let Some(new_ticket_nft) = changes.ticket_nft {
+        config.ticket_nft = Some(api.addr_validate(&new_ticket_nft)?);
+    }
+
+    if let Some(new_allowlist) = changes.allowlist {
+        config.allowlist = ensure_allowlist_is_valid(api, new_allowlist)?;
+    }
+
+    if let Some(new_max_tickets_per_draw) = changes.max_tickets_per_draw {
+        config.max_tickets_per_draw = Some(new_max_tickets_per_draw);
+    }
+
+    if let Some(new_min_participation) = changes.min_participation {
+        config.min_participation = Some(new_min_participation);
+    }
+
+    if let Some(new_pricing) = changes.pricing {
+        ensure_pricing_is_valid(&new_pricing)?;
+        config.pricing = Some(new_pricing);
+    }
+
+    if let Some(new_randomness_fee_bps) = changes.randomness_fee_bps {
+        ensure_bps_is_valid(new_randomness_fee_bps)?;
+        config.randomness_fee_bps = Some(new_randomness_fee_bps);
+    }
+
+    if let Some(new_auto_raffle_max_tickets) = changes.auto_raffle_max_tickets {
+        config.auto_raffle_max_tickets = Some(new_auto_raffle_max_tickets);
+    }
+
+    if let Some(new_keeper_bounty) = changes.keeper_bounty {
+        ensure_keeper_bounty_is_valid(&new_keeper_bounty)?;
+        config.keeper_bounty = Some(new_keeper_bounty);
+    }
+
+    if let Some(new_schedule_anchor) = changes.schedule_anchor {
+        config.schedule_anchor = Some(new_schedule_anchor);
+    }
+
+    // The interval may have changed as well
+    if let Some(schedule_anchor) = &config.schedule_anchor {
+        ensure_schedule_is_valid(schedule_anchor, &config.interval)?;
+    }
+
+    ensure_prize_tiers_are_valid(&config.tiers())?;
+    ensure_sales_shares_are_valid(config)?;
+
+    Ok(())
+}
+
+/// Lists the fields that differ between two configs, with their JSON encoded values
+pub fn diff_config(old: &Config, new: &Config) -> StdResult<Vec<ConfigChange>> {
+    let mut changes = vec![];
+
+    macro_rules! diff {
+        ($($field:ident),*) => {
+            $(
+                if old.$field != new.$field {
+                    changes.push(ConfigChange {
+                        field: stringify!($field).to_string(),
+                        old: String::from_utf8_lossy(&to_vec(&old.$field)?).into_owned(),
+                        new: String::from_utf8_lossy(&to_vec(&new.$field)?).into_owned(),
+                    });
+                }
+            )*
+        };
+    }
+
+    diff!(
+        owner,
+        interval,
+        treasury_fee,
+        ticket_price,
+        nois_proxy,
+        request_timeout,
+        percentage_per_match,
+        max_tickets_per_user,
+        min_jackpot,
+        jackpot_cap,
+        prize_tiers,
+        no_loss,
+        config_timelock,
+        paused,
+        pricing,
+        referral_bps,
+        voucher_pubkey,
+        loyalty,
+        second_chance,
+        progressive_jackpot_bps,
+        ticket_nft,
+        allowlist,
+        max_tickets_per_draw,
+        min_participation,
+        schedule_anchor,
+        keeper_bounty,
+        randomness_fee_bps,
+        auto_raffle_max_tickets
+    );
+
+    Ok(changes)
+}
+
+/// Appends the changes to the config history, returning them for the event
+pub fn record_config_change(
+    storage: &mut dyn Storage,
+    env: &Env,
+    updated_by: Addr,
+    old: &Config,
+    new: &Config,
+) -> Result<Vec<ConfigChange>, ContractError> {
+    let changes = diff_config(old, new)?;
+
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+
+    let version = CONFIG_HISTORY
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(Ok(1), |last| checked_add_u64(last, 1))?;
+
+    CONFIG_HISTORY.save(
+        storage,
+        version,
+        &ConfigHistoryEntry {
+            version,
+            updated_by,
+            updated_at: env.block.time,
+            changes: changes.clone(),
+        },
+    )?;
+
+    Ok(changes)
+}
+
+pub fn add_randomness_fees(storage: &mut dyn Storage, fees: &Coin) -> StdResult<()> {
+    let balance = RANDOMNESS_FEES
+        .may_load(storage, &fees.denom)?
+        .unwrap_or_default();
+    RANDOMNESS_FEES.save(storage, &fees.denom, &balance.checked_add(fees.amount)?)
+}
+
+/// Tops up the prepaid randomness fees with funds sent by a caller, which must all be in
+/// denoms the Nois proxy accepts so they can't get stuck in the balance
+pub fn add_sent_randomness_fees(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    nois_proxy: &Addr,
+    funds: &[Coin],
+) -> Result<(), ContractError> {
+    if funds.is_empty() {
+        return Ok(());
+    }
+
+    let response: NoisPricesResponse =
+        querier.query_wasm_smart(nois_proxy, &NoisProxyQueryMsg::Prices {})?;
+
+    if funds.iter().any(|fund| {
+        !response
+            .prices
+            .iter()
+            .any(|price| price.denom == fund.denom)
+    }) {
+        return Err(ContractError::InvalidCoin);
+    }
+
+    for fund in funds {
+        add_randomness_fees(storage, fund)?;
+    }
+
+    Ok(())
+}
+
+/// Takes the Nois fee out of the prepaid randomness fees, in the first denom accepted
+/// by the proxy that the balance covers
+pub fn take_randomness_fee(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    nois_proxy: &Addr,
+) -> Result<Vec<Coin>, ContractError> {
+    let response: NoisPricesResponse =
+        querier.query_wasm_smart(nois_proxy, &NoisProxyQueryMsg::Prices {})?;
+
+    if response.prices.is_empty() {
+        return Ok(vec![]);
+    }
+
+    for price in response.prices {
+        let balance = RANDOMNESS_FEES
+            .may_load(storage, &price.denom)?
+            .unwrap_or_default();
+
+        if balance >= price.amount {
+            RANDOMNESS_FEES.save(storage, &price.denom, &(balance - price.amount))?;
+            return Ok(vec![price]);
+        }
+    }
+
+    Err(ContractError::RandomnessFeesUnderfunded)
+}
+
+pub fn ensure_keeper_bounty_is_valid(bounty: &KeeperBounty) -> Result<(), ContractError> {
+    match bounty {
+        KeeperBounty::Fixed(_) => Ok(()),
+        KeeperBounty::Bps { bps, .. } => ensure_bps_is_valid(*bps),
+    }
+}
+
+/// Takes a keeper bounty out of the draw's pot, nothing when it was already paid for
+/// that stage
+pub fn take_keeper_bounty(
+    storage: &mut dyn Storage,
+    config: &Config,
+    draw: &mut Draw,
+    already_paid: bool,
+) -> Result<Option<Coin>, ContractError> {
+    let bounty = match &config.keeper_bounty {
+        Some(bounty) if !already_paid => bounty,
+        _ => return Ok(None),
+    };
+
+    let pot = draw.total_prize.amount;
+    let amount = match bounty {
+        KeeperBounty::Fixed(amount) => *amount,
+        KeeperBounty::Bps { bps, cap } => {
+            let amount = pot.checked_multiply_ratio(*bps, BPS_DENOMINATOR)?;
+            cap.map_or(amount, |cap| amount.min(cap))
+        }
+    }
+    .min(pot);
+
+    draw.total_prize.amount = pot.checked_sub(amount)?;
+    update_prize_per_match(storage, config, draw)?;
+
+    update_liabilities(storage, |mut liabilities| {
+        liabilities.open_pots = liabilities.open_pots.checked_sub(amount)?;
+        Ok(liabilities)
+    })?;
+
+    Ok(Some(coin(amount.u128(), &draw.total_prize.denom)))
+}
+
+pub fn ensure_schedule_is_valid(
+    anchor: &Expiration,
+    interval: &Duration,
+) -> Result<(), ContractError> {
+    match (anchor, interval) {
+        (Expiration::AtTime(_), Duration::Time(interval))
+        | (Expiration::AtHeight(_), Duration::Height(interval))
+            if *interval > 0 =>
+        {
+            Ok(())
+        }
+        _ => Err(ContractError::InvalidSchedule),
+    }
+}
+
+/// First slot `anchor + k * interval` strictly after `now`, `k` being any integer so
+/// missed slots are skipped rather than caught up on
+pub fn next_slot(anchor: u64, interval: u64, now: u64) -> Result<u64, ContractError> {
+    if now >= anchor {
+        let k = checked_add_u64((now - anchor) / interval, 1)?;
+        let offset = k
+            .checked_mul(interval)
+            .ok_or_else(|| OverflowError::new(OverflowOperation::Mul, k, interval))?;
+        Ok(checked_add_u64(anchor, offset)?)
+    } else {
+        Ok(anchor - (anchor - now - 1) / interval * interval)
+    }
+}
+
+/// End time of a draw opened now, on the schedule when it is anchored
+pub fn next_draw_end_time(config: &Config, env: &Env) -> Result<Expiration, ContractError> {
+    match (&config.schedule_anchor, config.interval) {
+        (None, interval) => Ok(build_expiration_time(env, interval)?),
+        (Some(Expiration::AtTime(anchor)), Duration::Time(interval)) => {
+            Ok(Expiration::AtTime(Timestamp::from_seconds(next_slot(
+                anchor.seconds(),
+                interval,
+                env.block.time.seconds(),
+            )?)))
+        }
+        (Some(Expiration::AtHeight(anchor)), Duration::Height(interval)) => Ok(
+            Expiration::AtHeight(next_slot(*anchor, interval, env.block.height)?),
+        ),
+        _ => Err(ContractError::InvalidSchedule),
+    }
+}
+
 pub fn build_expiration_time(env: &Env, duration: Duration) -> StdResult<Expiration> {
     match duration {
         Duration::Height(_) => Expiration::AtHeight(env.block.height).add(duration),
@@ -0,0 +1,100 @@
+use cosmwasm_std::{Binary, Coin, CosmosMsg, Timestamp};
+
+const MSG_TRANSFER_TYPE_URL: &str = "/ibc.applications.transfer.v1.MsgTransfer";
+
+/// ICS-20 transfer out of the `transfer` port. Unlike `IbcMsg::Transfer` it carries a
+/// memo, which is how ibc-hooks is asked for a callback once the transfer is done
+pub fn transfer_msg(
+    channel: &str,
+    sender: &str,
+    receiver: &str,
+    amount: &Coin,
+    timeout: Timestamp,
+    memo: &str,
+) -> CosmosMsg {
+    let mut token = vec![];
+    encode_string(&mut token, 1, &amount.denom);
+    encode_string(&mut token, 2, &amount.amount.to_string());
+
+    let mut msg = vec![];
+    encode_string(&mut msg, 1, "transfer");
+    encode_string(&mut msg, 2, channel);
+    encode_bytes(&mut msg, 3, &token);
+    encode_string(&mut msg, 4, sender);
+    encode_string(&mut msg, 5, receiver);
+    encode_uint64(&mut msg, 7, timeout.nanos());
+    encode_string(&mut msg, 8, memo);
+
+    CosmosMsg::Stargate {
+        type_url: MSG_TRANSFER_TYPE_URL.to_string(),
+        value: Binary::from(msg),
+    }
+}
+
+/// Sequence of the packet a transfer was sent in, read from its `MsgTransferResponse`
+pub fn transfer_sequence(mut data: &[u8]) -> Option<u64> {
+    while !data.is_empty() {
+        let tag = decode_varint(&mut data)?;
+
+        match (tag >> 3, tag & 7) {
+            (1, 0) => return decode_varint(&mut data),
+            (_, 0) => {
+                decode_varint(&mut data)?;
+            }
+            (_, 2) => {
+                let len = usize::try_from(decode_varint(&mut data)?).ok()?;
+                data = data.get(len..)?;
+            }
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_uint64(buf: &mut Vec<u8>, field: u64, value: u64) {
+    if value == 0 {
+        return;
+    }
+
+    encode_varint(buf, field << 3);
+    encode_varint(buf, value);
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+
+    encode_varint(buf, field << 3 | 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_string(buf: &mut Vec<u8>, field: u64, value: &str) {
+    encode_bytes(buf, field, value.as_bytes());
+}
+
+fn decode_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
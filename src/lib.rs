@@ -4,6 +4,8 @@ pub mod state;
 
 mod error;
 mod helpers;
+mod ibc;
+mod pricing;
 
 #[cfg(test)]
 mod tests;
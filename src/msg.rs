@@ -1,9 +1,14 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::Coin;
-use cw_utils::Duration;
+use cosmwasm_std::{Addr, Binary, Coin, Timestamp, Uint128};
+use cw_utils::{Duration, Expiration};
 use nois::NoisCallback;
 
-use crate::state::{Config, Draw, TicketResult};
+use crate::state::{
+    Allowlist, Config, ConfigHistoryEntry, ConfigUpdate, Draw, GamingLimits, JackpotCap,
+    KeeperBounty, Liabilities, LoyaltyConfig, LoyaltyPoints, MinParticipation, NoLossConfig,
+    PendingConfig, Pricing, PrizeTier, Purchase, ReferralStats, RemoteAccount, SecondChanceConfig,
+    SecondChanceWinner, Sponsorship, TicketResult, Unbonding,
+};
 
 #[cw_serde]
 pub struct MigrateMsg {}
@@ -16,17 +21,150 @@ pub struct InstantiateMsg {
     pub draw_interval: Duration,
     pub nois_proxy: String,
     pub max_tickets_per_user: u32,
-    pub percentage_per_match: [u8; 6],
+    /// Only allowed without `prize_tiers`
+    pub percentage_per_match: Option<[u8; 6]>,
+    pub min_jackpot: Option<Uint128>,
+    pub jackpot_cap: Option<JackpotCap>,
+    pub prize_tiers: Option<[PrizeTier; 6]>,
+    pub no_loss: Option<NoLossConfig>,
+    pub config_timelock: Option<Duration>,
+    pub pricing: Option<Pricing>,
+    pub referral_bps: Option<u16>,
+    pub voucher_pubkey: Option<Binary>,
+    pub loyalty: Option<LoyaltyConfig>,
+    pub second_chance: Option<SecondChanceConfig>,
+    pub progressive_jackpot_bps: Option<u16>,
+    pub ticket_nft: Option<String>,
+    pub allowlist: Option<Allowlist>,
+    pub max_tickets_per_draw: Option<u64>,
+    pub min_participation: Option<MinParticipation>,
+    pub schedule_anchor: Option<Expiration>,
+    pub keeper_bounty: Option<KeeperBounty>,
+    pub randomness_fee_bps: Option<u16>,
+    pub auto_raffle_max_tickets: Option<u64>,
 }
 
 #[cw_serde]
 pub enum ExecuteMsg {
-    NoisReceive { callback: NoisCallback },
-    RequestRandomness { draw_id: u64 },
-    BuyTickets { tickets: Vec<String>, draw_id: u64 },
-    Raffle { draw_id: u64 },
-    ClaimPrize { draw_id: u64 },
-    UpdateConfig { new_config: UpdateConfigMsg },
+    NoisReceive {
+        callback: NoisCallback,
+    },
+    RequestRandomness {
+        draw_id: u64,
+    },
+    BuyTickets {
+        tickets: Vec<String>,
+        draw_id: u64,
+        referrer: Option<String>,
+        voucher: Option<Voucher>,
+    },
+    Raffle {
+        draw_id: u64,
+        /// Depositors gone through at once by a no-loss draw
+        limit: Option<u32>,
+    },
+    ClaimPrize {
+        draw_id: u64,
+        /// Claims the tickets of these NFTs instead of the sender's own tickets
+        token_ids: Option<Vec<String>>,
+    },
+    UpdateConfig {
+        new_config: Box<UpdateConfigMsg>,
+    },
+    FundPot {
+        draw_id: u64,
+    },
+    FundReserve {},
+    RecoverSurplus {
+        amount: Uint128,
+        recipient: Option<String>,
+    },
+    Deposit {},
+    Withdraw {
+        amount: Uint128,
+    },
+    ClaimUnbonded {},
+    ProposeConfigChange {
+        new_config: Box<UpdateConfigMsg>,
+    },
+    ApplyConfigChange {},
+    CancelConfigChange {},
+    /// Pays the credits of raffled draws, each as far as its draw's treasury fee goes
+    ClaimReferralRewards {},
+    FundPromoBudget {},
+    FundLoyaltyReserve {},
+    RedeemPoints {
+        tickets: Vec<String>,
+        draw_id: u64,
+    },
+    RequestSecondChance {
+        draw_id: u64,
+    },
+    /// Goes through the next `limit` ticket holders of a second-chance draw, paying the
+    /// winners after the last one
+    RaffleSecondChance {
+        draw_id: u64,
+        limit: Option<u32>,
+    },
+    TransferTickets {
+        draw_id: u64,
+        tickets: Vec<String>,
+        recipient: String,
+    },
+    MintTickets {
+        draw_id: u64,
+        tickets: Vec<String>,
+    },
+    /// Sent through an ICS-20 wasm-hooks memo, the sender being the intermediate
+    /// address derived from `channel` and `remote_sender`
+    IbcBuyTickets {
+        tickets: Vec<String>,
+        draw_id: u64,
+        channel: String,
+        remote_sender: String,
+    },
+    UpdateAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    UpdateDenylist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    SetGamingLimits {
+        daily_limit: Option<Uint128>,
+        weekly_limit: Option<Uint128>,
+        cooldown: Option<u64>,
+    },
+    SelfExclude {
+        until: Timestamp,
+    },
+    ClaimRefund {
+        draw_id: u64,
+    },
+    FundRandomnessFees {},
+    /// Sends the sender's failed IBC payouts again
+    RetryPayouts {},
+}
+
+/// Called by ibc-hooks on the contract that asked for a callback in a transfer memo
+#[cw_serde]
+pub enum SudoMsg {
+    IbcLifecycleComplete(IbcLifecycleComplete),
+}
+
+#[cw_serde]
+pub enum IbcLifecycleComplete {
+    IbcAck {
+        channel: String,
+        sequence: u64,
+        ack: String,
+        success: bool,
+    },
+    IbcTimeout {
+        channel: String,
+        sequence: u64,
+    },
 }
 
 #[cw_serde]
@@ -42,16 +180,201 @@ pub enum QueryMsg {
     GetTickets { addr: String, draw_id: u64 },
     #[returns(Config)]
     GetConfig {},
+    #[returns(Vec<Sponsorship>)]
+    GetSponsors { draw_id: u64 },
+    #[returns(Coin)]
+    GetReserve {},
+    #[returns(SolvencyResponse)]
+    GetSolvency {},
+    #[returns(DepositResponse)]
+    GetDeposit { addr: String },
+    #[returns(Option<PendingConfig>)]
+    GetPendingConfig {},
+    #[returns(Vec<ConfigHistoryEntry>)]
+    GetConfigHistory {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(PriceQuote)]
+    QuoteTickets { n_tickets: u32 },
+    #[returns(Vec<Purchase>)]
+    GetPurchases { addr: String, draw_id: u64 },
+    #[returns(ReferralStatsResponse)]
+    GetReferralStats { addr: String },
+    #[returns(Coin)]
+    GetPromoBudget {},
+    #[returns(bool)]
+    IsVoucherUsed { nonce: String },
+    #[returns(LoyaltyPoints)]
+    GetLoyaltyPoints { addr: String },
+    #[returns(Coin)]
+    GetLoyaltyReserve {},
+    #[returns(Vec<SecondChanceWinner>)]
+    GetSecondChanceWinners { draw_id: u64 },
+    #[returns(Coin)]
+    GetJackpot {},
+    #[returns(Vec<String>)]
+    GetNftTickets { draw_id: u64, token_id: String },
+    #[returns(Option<RemoteAccount>)]
+    GetRemoteAccount { addr: String },
+    /// Whether the address may buy tickets and claim prizes
+    #[returns(bool)]
+    IsAllowed { addr: String },
+    #[returns(GamingLimitsResponse)]
+    GetGamingLimits { addr: String },
+    #[returns(Coin)]
+    GetRefund { draw_id: u64, addr: String },
+    /// Upcoming draw end times
+    #[returns(Vec<Expiration>)]
+    GetSchedule { limit: Option<u32> },
+    #[returns(Vec<Coin>)]
+    GetRandomnessFees {},
+    /// IBC payouts that failed and can be sent again
+    #[returns(Vec<Coin>)]
+    GetFailedPayouts { addr: String },
 }
 
+/// Changes to the config, fields left out keep their current value
+pub type UpdateConfigMsg = ConfigUpdate;
+
 #[cw_serde]
-pub struct UpdateConfigMsg {
-    pub treasury_fee: Option<u8>,
-    pub owner: Option<String>,
-    pub ticket_price: Option<Coin>,
-    pub interval: Option<Duration>,
-    pub request_timeout: Option<Duration>,
-    pub nois_proxy: Option<String>,
-    pub max_tickets_per_user: Option<u32>,
-    pub percentage_per_match: Option<[u8; 6]>,
+pub struct SolvencyResponse {
+    pub balance: Coin,
+    pub liabilities: Liabilities,
+    pub reserve: Uint128,
+    pub promo_budget: Uint128,
+    pub loyalty_reserve: Uint128,
+    pub jackpot: Uint128,
+    pub randomness_fees: Uint128,
+    /// Principal still unbonding, counted along with the balance
+    pub unbonding: Uint128,
+    pub total_liabilities: Uint128,
+    pub surplus: Uint128,
+    pub deficit: Uint128,
+}
+
+#[cw_serde]
+pub struct GamingLimitsResponse {
+    pub limits: GamingLimits,
+    pub spent_last_day: Uint128,
+    pub spent_last_week: Uint128,
+    /// When the cooldown or the self-exclusion allows buying again
+    pub next_purchase_at: Option<Timestamp>,
+}
+
+#[cw_serde]
+pub struct DepositResponse {
+    pub principal: Uint128,
+    /// Weight accumulated in the current draw so far
+    pub weight: Uint128,
+    pub unbonding: Vec<Unbonding>,
+}
+
+#[cw_serde]
+pub struct PriceQuote {
+    pub tickets: u32,
+    pub free_tickets: u32,
+    pub volume_discount_bps: u16,
+    pub window_discount_bps: u16,
+    pub price: Coin,
+}
+
+#[cw_serde]
+pub struct ReferralStatsResponse {
+    pub referrer: Option<Addr>,
+    pub stats: ReferralStats,
+    /// Credits from raffled draws, ready to be claimed. This is what the claim pays: a draw's
+    /// credits are funded out of its treasury fee alone, and scaled down when it falls short
+    pub claimable: Uint128,
+    /// Credits from draws not raffled yet, at most what they will pay once funded
+    pub pending: Uint128,
+}
+
+/// Voucher signed off-chain by the owner's voucher key
+#[cw_serde]
+pub struct Voucher {
+    pub nonce: String,
+    pub discount_bps: u16,
+    /// Most the promo budget covers, whatever the size of the purchase
+    pub max_subsidy: Uint128,
+    pub expires_at: Timestamp,
+    /// Only this address may redeem the voucher when set
+    pub recipient: Option<String>,
+    /// Signature over the sha256 hash of the JSON encoded `VoucherPayload`
+    pub signature: Binary,
+}
+
+#[cw_serde]
+pub struct VoucherPayload {
+    pub contract: String,
+    pub nonce: String,
+    pub discount_bps: u16,
+    pub max_subsidy: Uint128,
+    pub expires_at: Timestamp,
+    pub recipient: Option<String>,
+}
+
+#[cw_serde]
+pub struct TicketMetadata {
+    /// Draw the tickets were minted in, a rollover moves them on to the next draw
+    pub draw_id: u64,
+    pub numbers: Vec<String>,
+}
+
+/// Subset of the CW721 execute messages used to mint tickets
+#[cw_serde]
+pub enum Cw721ExecuteMsg {
+    Mint {
+        token_id: String,
+        owner: String,
+        token_uri: Option<String>,
+        extension: TicketMetadata,
+    },
+}
+
+/// Subset of the CW721 queries used to find who holds a ticket
+#[cw_serde]
+pub enum Cw721QueryMsg {
+    OwnerOf {
+        token_id: String,
+        include_expired: Option<bool>,
+    },
+}
+
+#[cw_serde]
+pub struct Cw721Approval {
+    pub spender: String,
+    pub expires: Expiration,
+}
+
+#[cw_serde]
+pub struct OwnerOfResponse {
+    pub owner: String,
+    pub approvals: Vec<Cw721Approval>,
+}
+
+/// Subset of the cw4 queries used to check group membership
+#[cw_serde]
+pub enum Cw4QueryMsg {
+    Member {
+        addr: String,
+        at_height: Option<u64>,
+    },
+}
+
+#[cw_serde]
+pub struct MemberResponse {
+    pub weight: Option<u64>,
+}
+
+/// Subset of the Nois proxy queries used to pay for randomness
+#[cw_serde]
+pub enum NoisProxyQueryMsg {
+    Prices {},
+}
+
+#[cw_serde]
+pub struct NoisPricesResponse {
+    /// Any of these pays for a request, none means randomness is free
+    pub prices: Vec<Coin>,
 }
@@ -0,0 +1,78 @@
+use cosmwasm_std::{coin, Coin, Timestamp, Uint128};
+
+use crate::error::ContractError;
+use crate::helpers::BPS_DENOMINATOR;
+use crate::msg::PriceQuote;
+use crate::state::Pricing;
+
+pub fn ensure_pricing_is_valid(pricing: &Pricing) -> Result<(), ContractError> {
+    let bps_in_range = pricing
+        .volume_tiers
+        .iter()
+        .map(|t| t.discount_bps)
+        .chain(pricing.windows.iter().map(|w| w.discount_bps))
+        .all(|bps| u128::from(bps) <= BPS_DENOMINATOR);
+
+    let windows_in_order = pricing.windows.iter().all(|w| w.start < w.end);
+
+    let bundle_is_valid = pricing.bundle.as_ref().map_or(true, |b| b.buy > 0);
+
+    if !bps_in_range || !windows_in_order || !bundle_is_valid {
+        return Err(ContractError::InvalidPricing);
+    }
+
+    Ok(())
+}
+
+/// Prices a purchase of `n_tickets`. Free bundle tickets are taken out first, then the
+/// best volume discount and the best active window discount are applied one after the other
+pub fn quote_tickets(
+    ticket_price: &Coin,
+    pricing: Option<&Pricing>,
+    n_tickets: u32,
+    now: Timestamp,
+) -> Result<PriceQuote, ContractError> {
+    let pricing = pricing.cloned().unwrap_or_default();
+
+    let free_tickets = match &pricing.bundle {
+        Some(bundle) => (n_tickets / bundle.buy.saturating_add(bundle.free)) * bundle.free,
+        None => 0,
+    };
+
+    let volume_discount_bps = pricing
+        .volume_tiers
+        .iter()
+        .filter(|t| n_tickets >= t.min_tickets)
+        .map(|t| t.discount_bps)
+        .max()
+        .unwrap_or_default();
+
+    let window_discount_bps = pricing
+        .windows
+        .iter()
+        .filter(|w| w.start <= now && now < w.end)
+        .map(|w| w.discount_bps)
+        .max()
+        .unwrap_or_default();
+
+    let full_price = ticket_price
+        .amount
+        .checked_mul(Uint128::from(n_tickets - free_tickets))?;
+
+    let price =
+        [volume_discount_bps, window_discount_bps].iter().try_fold(
+            full_price,
+            |price, bps| -> Result<Uint128, ContractError> {
+                Ok(price
+                    .checked_multiply_ratio(BPS_DENOMINATOR - u128::from(*bps), BPS_DENOMINATOR)?)
+            },
+        )?;
+
+    Ok(PriceQuote {
+        tickets: n_tickets,
+        free_tickets,
+        volume_discount_bps,
+        window_discount_bps,
+        price: coin(price.u128(), &ticket_price.denom),
+    })
+}
@@ -1,5 +1,7 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{coin, Addr, CanonicalAddr, Coin, Uint128};
+use cosmwasm_std::{
+    coin, Addr, Binary, CanonicalAddr, Coin, Empty, HexBinary, OverflowError, Timestamp, Uint128,
+};
 use cw_storage_plus::{Item, Map};
 use cw_utils::{Duration, Expiration};
 
@@ -9,6 +11,142 @@ pub enum Status {
     Pending,
     Raffling,
     Claimable,
+    /// Not raffled for lack of participants, its tickets are refunded or carried over
+    Cancelled,
+}
+
+/// Progress of the second-chance draw once the main draw is claimable
+#[cw_serde]
+pub enum SecondChanceStatus {
+    Pending,
+    Requested,
+    /// Randomness received, the tickets are being gone through
+    Raffling,
+    Settled,
+}
+
+#[cw_serde]
+pub enum OverflowPolicy {
+    /// Adds the excess to the five matches tier
+    NextTier,
+    /// Moves the excess into the prize reserve
+    Reserve,
+    /// Shares the excess among this draw's lower tiers that have winners
+    LowerTierWinners,
+}
+
+#[cw_serde]
+pub struct JackpotCap {
+    pub amount: Uint128,
+    pub overflow: OverflowPolicy,
+}
+
+#[cw_serde]
+#[derive(Copy)]
+pub enum PrizeTier {
+    /// Pays every winner of the tier this amount
+    Fixed(Uint128),
+    /// Shares this fraction of the pot, in basis points, among the tier winners
+    Share(u16),
+}
+
+#[cw_serde]
+pub struct VolumeTier {
+    pub min_tickets: u32,
+    pub discount_bps: u16,
+}
+
+/// Every `buy` tickets paid for come with `free` extra tickets
+#[cw_serde]
+pub struct Bundle {
+    pub buy: u32,
+    pub free: u32,
+}
+
+/// Discount applied to purchases made between `start` (inclusive) and `end`
+#[cw_serde]
+pub struct DiscountWindow {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub discount_bps: u16,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct Pricing {
+    pub volume_tiers: Vec<VolumeTier>,
+    pub bundle: Option<Bundle>,
+    pub windows: Vec<DiscountWindow>,
+}
+
+/// Who may buy tickets and claim prizes, on top of the denylist
+#[cw_serde]
+#[derive(Default)]
+pub enum Allowlist {
+    #[default]
+    Disabled,
+    /// Addresses added by the owner
+    Static,
+    /// Members of a cw4 group contract
+    Group { contract: Addr },
+}
+
+/// Reward for whoever requests the randomness or raffles a draw, taken from its pot
+#[cw_serde]
+pub enum KeeperBounty {
+    Fixed(Uint128),
+    /// Fraction of the pot in basis points, up to `cap`
+    Bps {
+        bps: u16,
+        cap: Option<Uint128>,
+    },
+}
+
+#[cw_serde]
+pub enum UnderSubscribedPolicy {
+    /// Keeps the draw open for another interval
+    Extend,
+    /// Cancels the draw and lets buyers claim back what they paid
+    Refund,
+    /// Cancels the draw and carries its tickets and pot over to the next one. Minted ticket
+    /// tokens keep the cancelled draw in their metadata, but are claimed in the next one
+    Rollover,
+}
+
+/// Minimum number of tickets a draw must sell to be raffled
+#[cw_serde]
+pub struct MinParticipation {
+    pub tickets: u64,
+    pub policy: UnderSubscribedPolicy,
+}
+
+/// Sets aside a share of the sales for tickets that matched nothing
+#[cw_serde]
+pub struct SecondChanceConfig {
+    pub pool_bps: u16,
+    /// Number of tickets picked
+    pub winners: u32,
+    /// Prize of every picked ticket, as far as the pool goes. Without it the picked
+    /// tickets share the pool equally
+    #[serde(default)]
+    pub prize: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct LoyaltyConfig {
+    /// Points earned for every ticket bought
+    pub points_per_ticket: u64,
+    /// Points it takes to redeem a free ticket
+    pub points_per_free_ticket: u64,
+}
+
+/// Turns the contract into a no-loss lottery: deposits are staked and only the
+/// staking rewards are raffled, weighted by deposit and time
+#[cw_serde]
+pub struct NoLossConfig {
+    pub validators: Vec<String>,
+    /// Unbonding time of the chain, in seconds
+    pub unbonding_period: u64,
 }
 
 #[cw_serde]
@@ -19,8 +157,52 @@ pub struct Config {
     pub ticket_price: Coin,
     pub nois_proxy: Addr,
     pub request_timeout: Duration,
+    /// Mirrors the shared tiers, rounded down, once `prize_tiers` is set
     pub percentage_per_match: [u8; 6],
     pub max_tickets_per_user: u32,
+    pub min_jackpot: Option<Uint128>,
+    pub jackpot_cap: Option<JackpotCap>,
+    pub prize_tiers: Option<[PrizeTier; 6]>,
+    pub no_loss: Option<NoLossConfig>,
+    /// Delay before a proposed config change can be applied
+    pub config_timelock: Option<Duration>,
+    /// Emergency switch stopping sales and deposits, exempt from the timelock
+    #[serde(default)]
+    pub paused: bool,
+    pub pricing: Option<Pricing>,
+    /// Share of each sale credited to the buyer's referrer, paid out of the treasury fee
+    pub referral_bps: Option<u16>,
+    /// secp256k1 public key that signs ticket vouchers
+    pub voucher_pubkey: Option<Binary>,
+    pub loyalty: Option<LoyaltyConfig>,
+    pub second_chance: Option<SecondChanceConfig>,
+    /// Share of every sale feeding the progressive jackpot
+    pub progressive_jackpot_bps: Option<u16>,
+    /// Companion CW721 contract minting tickets, this contract must be its minter
+    pub ticket_nft: Option<Addr>,
+    #[serde(default)]
+    pub allowlist: Allowlist,
+    /// Sales close once a draw has sold that many tickets
+    pub max_tickets_per_draw: Option<u64>,
+    pub min_participation: Option<MinParticipation>,
+    /// Draws end on `anchor + k * interval` instead of an interval after the last raffle,
+    /// given as a time or a height matching the interval
+    pub schedule_anchor: Option<Expiration>,
+    pub keeper_bounty: Option<KeeperBounty>,
+    /// Share of every sale topping up the prepaid randomness fees
+    pub randomness_fee_bps: Option<u16>,
+    /// Draws with at most that many tickets are raffled in the Nois callback
+    pub auto_raffle_max_tickets: Option<u64>,
+}
+
+impl Config {
+    /// Falls back to pari-mutuel tiers built from `percentage_per_match`
+    pub fn tiers(&self) -> [PrizeTier; 6] {
+        self.prize_tiers.unwrap_or_else(|| {
+            self.percentage_per_match
+                .map(|p| PrizeTier::Share(u16::from(p) * 100))
+        })
+    }
 }
 
 #[cw_serde]
@@ -34,6 +216,36 @@ pub struct Draw {
     pub total_tickets: u64,
     pub prize_per_match: Option<[Uint128; 6]>,
     pub winners_per_match: Option<[u64; 6]>,
+    #[serde(default)]
+    pub jackpot_subsidy: Uint128,
+    #[serde(default)]
+    pub jackpot_overflow: Uint128,
+    #[serde(default)]
+    pub dust: Uint128,
+    #[serde(default)]
+    pub opened_at: Timestamp,
+    pub randomness: Option<HexBinary>,
+    /// Referral credits earned on this draw's sales
+    #[serde(default)]
+    pub referral_rewards: Uint128,
+    /// Part of the credits actually funded at raffle time
+    #[serde(default)]
+    pub referral_funded: Uint128,
+    #[serde(default)]
+    pub second_chance_pool: Uint128,
+    pub second_chance_status: Option<SecondChanceStatus>,
+    /// Progressive jackpot added to the six matches tier
+    #[serde(default)]
+    pub progressive_jackpot: Uint128,
+    /// Part of this draw's sales that went into the progressive jackpot
+    #[serde(default)]
+    pub jackpot_contribution: Uint128,
+    /// Part of this draw's sales that went into the randomness fees
+    #[serde(default)]
+    pub randomness_fee_contribution: Uint128,
+    /// Keeper bounties taken from the pot, set once paid
+    pub request_bounty: Option<Coin>,
+    pub raffle_bounty: Option<Coin>,
 }
 
 impl Draw {
@@ -42,9 +254,9 @@ impl Draw {
         end_time: Expiration,
         ticket_price: Coin,
         inital_prize: Uint128,
-        prize_per_match: Option<[Uint128; 6]>,
+        opened_at: Timestamp,
     ) -> Self {
-        return Draw {
+        Draw {
             id,
             status: Status::Open,
             end_time,
@@ -52,9 +264,23 @@ impl Draw {
             ticket_price: ticket_price.clone(),
             total_prize: coin(inital_prize.u128(), ticket_price.denom),
             total_tickets: 0u64,
-            prize_per_match,
+            prize_per_match: None,
             winners_per_match: None,
-        };
+            jackpot_subsidy: Uint128::zero(),
+            jackpot_overflow: Uint128::zero(),
+            dust: Uint128::zero(),
+            opened_at,
+            randomness: None,
+            referral_rewards: Uint128::zero(),
+            referral_funded: Uint128::zero(),
+            second_chance_pool: Uint128::zero(),
+            second_chance_status: None,
+            progressive_jackpot: Uint128::zero(),
+            jackpot_contribution: Uint128::zero(),
+            randomness_fee_contribution: Uint128::zero(),
+            request_bounty: None,
+            raffle_bounty: None,
+        }
     }
 }
 
@@ -64,9 +290,299 @@ pub struct TicketResult {
     pub matches: u8,
 }
 
+/// Funds held by the contract on behalf of others, the prize reserve is tracked apart
+#[cw_serde]
+#[derive(Default)]
+pub struct Liabilities {
+    pub open_pots: Uint128,
+    pub unclaimed_prizes: Uint128,
+    pub accrued_fees: Uint128,
+    /// Principal undelegated by no-loss depositors and not claimed yet
+    #[serde(default)]
+    pub pending_withdrawals: Uint128,
+    /// Paid for tickets of cancelled draws and not claimed back yet
+    #[serde(default)]
+    pub refunds: Uint128,
+    /// Payouts that came back from a failed IBC transfer and wait to be sent again
+    #[serde(default)]
+    pub failed_payouts: Uint128,
+}
+
+impl Liabilities {
+    pub fn total(&self) -> Result<Uint128, OverflowError> {
+        self.open_pots
+            .checked_add(self.unclaimed_prizes)?
+            .checked_add(self.accrued_fees)?
+            .checked_add(self.pending_withdrawals)?
+            .checked_add(self.refunds)?
+            .checked_add(self.failed_payouts)
+    }
+}
+
+#[cw_serde]
+pub struct ConfigChange {
+    pub field: String,
+    /// JSON encoded values
+    pub old: String,
+    pub new: String,
+}
+
+#[cw_serde]
+pub struct ConfigHistoryEntry {
+    pub version: u64,
+    pub updated_by: Addr,
+    pub updated_at: Timestamp,
+    pub changes: Vec<ConfigChange>,
+}
+
+/// Changes to the config, as sent by the owner or held until a timelock is over
+#[cw_serde]
+#[derive(Default)]
+pub struct ConfigUpdate {
+    pub treasury_fee: Option<u8>,
+    pub owner: Option<String>,
+    pub ticket_price: Option<Coin>,
+    pub interval: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub nois_proxy: Option<String>,
+    pub max_tickets_per_user: Option<u32>,
+    /// Replaces the prize tiers, so it cannot come with `prize_tiers`
+    pub percentage_per_match: Option<[u8; 6]>,
+    pub min_jackpot: Option<Uint128>,
+    pub jackpot_cap: Option<JackpotCap>,
+    /// Removes the guaranteed minimum jackpot
+    #[serde(default)]
+    pub clear_min_jackpot: bool,
+    /// Removes the jackpot cap
+    #[serde(default)]
+    pub clear_jackpot_cap: bool,
+    pub prize_tiers: Option<[PrizeTier; 6]>,
+    pub config_timelock: Option<Duration>,
+    pub paused: Option<bool>,
+    pub pricing: Option<Pricing>,
+    pub referral_bps: Option<u16>,
+    pub voucher_pubkey: Option<Binary>,
+    pub loyalty: Option<LoyaltyConfig>,
+    pub second_chance: Option<SecondChanceConfig>,
+    pub progressive_jackpot_bps: Option<u16>,
+    pub ticket_nft: Option<String>,
+    pub allowlist: Option<Allowlist>,
+    pub max_tickets_per_draw: Option<u64>,
+    pub min_participation: Option<MinParticipation>,
+    pub schedule_anchor: Option<Expiration>,
+    pub keeper_bounty: Option<KeeperBounty>,
+    pub randomness_fee_bps: Option<u16>,
+    pub auto_raffle_max_tickets: Option<u64>,
+}
+
+impl ConfigUpdate {
+    /// Whether the update only touches fields exempt from the timelock
+    pub fn is_emergency_only(&self) -> bool {
+        self.paused.is_some()
+            && ConfigUpdate {
+                paused: None,
+                ..self.clone()
+            } == ConfigUpdate::default()
+    }
+}
+
+#[cw_serde]
+pub struct PendingConfig {
+    pub config: ConfigUpdate,
+    pub activates_at: Expiration,
+}
+
+#[cw_serde]
+pub struct Purchase {
+    pub tickets: u32,
+    pub free_tickets: u32,
+    /// What was actually paid after discounts
+    pub paid: Coin,
+    /// Part of the price covered by a voucher, out of the promo budget
+    #[serde(default)]
+    pub subsidy: Uint128,
+    /// Loyalty points spent, in which case the subsidy came from the loyalty reserve
+    #[serde(default)]
+    pub points: u64,
+}
+
+#[cw_serde]
+pub struct SecondChanceWinner {
+    pub addr: Addr,
+    pub ticket: String,
+    pub prize: Coin,
+}
+
+/// Who a ticket pays out to, either a wallet or the current owner of an NFT
+#[cw_serde]
+pub enum TicketHolder {
+    Wallet(Addr),
+    Nft(String),
+}
+
+#[cw_serde]
+pub struct SecondChancePick {
+    pub rank: HexBinary,
+    pub holder: TicketHolder,
+    pub ticket: String,
+}
+
+/// No-loss draw being raffled over several pages of depositors
+#[cw_serde]
+#[derive(Default)]
+pub struct NoLossRaffle {
+    /// Last depositor gone through
+    pub after: Option<Addr>,
+    /// Weight of the depositors gone through so far
+    pub total_weight: Uint128,
+    pub winner: Option<Addr>,
+}
+
+/// Second-chance draw being raffled over several pages of ticket holders
+#[cw_serde]
+pub struct SecondChanceRaffle {
+    pub randomness: HexBinary,
+    pub winners: u32,
+    pub prize: Option<Uint128>,
+    /// Last holder gone through, wallets come before tokens
+    pub after: Option<TicketHolder>,
+    /// Best ranked tickets so far, lowest rank first
+    pub picks: Vec<SecondChancePick>,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct LoyaltyPoints {
+    pub balance: u64,
+    pub lifetime: u64,
+    pub redeemed: u64,
+}
+
+/// Limits a player sets on themselves
+#[cw_serde]
+#[derive(Default)]
+pub struct GamingLimits {
+    /// Most that can be spent over any 24 hours
+    pub daily_limit: Option<Uint128>,
+    /// Most that can be spent over any 7 days
+    pub weekly_limit: Option<Uint128>,
+    /// Seconds to wait between two purchases
+    pub cooldown: Option<u64>,
+    /// Purchases are refused until then, it can be extended but never shortened
+    pub excluded_until: Option<Timestamp>,
+    /// Looser limits waiting for their delay to be over
+    pub pending: Option<PendingGamingLimits>,
+}
+
+#[cw_serde]
+pub struct PendingGamingLimits {
+    pub daily_limit: Option<Uint128>,
+    pub weekly_limit: Option<Uint128>,
+    pub cooldown: Option<u64>,
+    pub activates_at: Timestamp,
+}
+
+#[cw_serde]
+pub struct Spend {
+    pub at: Timestamp,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct ReferralStats {
+    pub referred_users: u64,
+    pub tickets_sold: u64,
+    /// Credits earned, less what the treasury fee of their draws could not fund
+    pub total_earned: Uint128,
+    pub total_claimed: Uint128,
+}
+
+/// Account on another chain buying through ibc-hooks, prizes are sent back to it
+#[cw_serde]
+pub struct RemoteAccount {
+    /// Channel on this chain the purchase came through
+    pub channel: String,
+    pub sender: String,
+}
+
+/// Payout sent back over IBC, kept until ibc-hooks reports whether it arrived
+#[cw_serde]
+pub struct IbcPayout {
+    pub channel: String,
+    pub winner: Addr,
+    pub amount: Coin,
+}
+
+#[cw_serde]
+pub struct Deposit {
+    pub principal: Uint128,
+    /// Principal multiplied by the seconds it was held during `draw_id`
+    pub weight: Uint128,
+    pub last_update: Timestamp,
+    pub draw_id: u64,
+}
+
+#[cw_serde]
+pub struct Unbonding {
+    pub amount: Uint128,
+    pub release_at: Timestamp,
+}
+
+#[cw_serde]
+pub struct Sponsorship {
+    pub sponsor: Addr,
+    pub amount: Uint128,
+}
+
 pub const CONFIG: Item<Config> = Item::new("config");
 pub const DRAWS_INDEX: Item<u64> = Item::new("draws_index");
 pub const DRAWS: Map<u64, Draw> = Map::new("draws");
 pub const WINNERS: Map<(u64, Addr), Coin> = Map::new("winners");
 pub const TICKETS: Map<(u64, Addr), Vec<String>> = Map::new("tickets");
 pub const REQUESTS: Map<u64, Expiration> = Map::new("requests");
+pub const SPONSORS: Map<(u64, Addr), Uint128> = Map::new("sponsors");
+pub const RESERVE: Item<Uint128> = Item::new("reserve");
+pub const LIABILITIES: Item<Liabilities> = Item::new("liabilities");
+pub const DEPOSITS: Map<Addr, Deposit> = Map::new("deposits");
+pub const UNBONDING: Map<Addr, Vec<Unbonding>> = Map::new("unbonding");
+/// Principal being unbonded, by the time in seconds it is back in the balance
+pub const UNBONDING_RELEASES: Map<u64, Uint128> = Map::new("unbonding_releases");
+pub const NO_LOSS_RAFFLES: Map<u64, NoLossRaffle> = Map::new("no_loss_raffles");
+pub const DELEGATIONS: Map<&str, Uint128> = Map::new("delegations");
+pub const PENDING_CONFIG: Item<PendingConfig> = Item::new("pending_config");
+pub const CONFIG_HISTORY: Map<u64, ConfigHistoryEntry> = Map::new("config_history");
+pub const PURCHASES: Map<(u64, Addr), Vec<Purchase>> = Map::new("purchases");
+pub const REFERRERS: Map<Addr, Addr> = Map::new("referrers");
+pub const REFERRAL_STATS: Map<Addr, ReferralStats> = Map::new("referral_stats");
+pub const REFERRAL_CREDITS: Map<(Addr, u64), Uint128> = Map::new("referral_credits");
+pub const PROMO_BUDGET: Item<Uint128> = Item::new("promo_budget");
+pub const USED_VOUCHERS: Map<&str, Addr> = Map::new("used_vouchers");
+pub const LOYALTY_POINTS: Map<Addr, LoyaltyPoints> = Map::new("loyalty_points");
+pub const LOYALTY_RESERVE: Item<Uint128> = Item::new("loyalty_reserve");
+pub const SECOND_CHANCE_REQUESTS: Map<u64, Expiration> = Map::new("second_chance_requests");
+pub const SECOND_CHANCE_WINNERS: Map<u64, Vec<SecondChanceWinner>> =
+    Map::new("second_chance_winners");
+pub const SECOND_CHANCE_RAFFLES: Map<u64, SecondChanceRaffle> = Map::new("second_chance_raffles");
+pub const JACKPOT: Item<Uint128> = Item::new("jackpot");
+pub const NFT_TOKEN_INDEX: Item<u64> = Item::new("nft_token_index");
+/// Tickets minted as NFTs, by draw and token id, held by whoever owns the token
+pub const NFT_TICKETS: Map<(u64, &str), Vec<String>> = Map::new("nft_tickets");
+pub const NFT_CLAIMS: Map<(u64, &str), Addr> = Map::new("nft_claims");
+/// Prepaid balance paying the Nois proxy, by denom
+pub const RANDOMNESS_FEES: Map<&str, Uint128> = Map::new("randomness_fees");
+pub const REFUNDS: Map<(u64, Addr), Uint128> = Map::new("refunds");
+pub const GAMING_LIMITS: Map<Addr, GamingLimits> = Map::new("gaming_limits");
+/// Purchases of the last 7 days up to the latest one, oldest first
+pub const SPEND_LEDGER: Map<Addr, Vec<Spend>> = Map::new("spend_ledger");
+pub const ALLOWLIST: Map<Addr, Empty> = Map::new("allowlist");
+pub const DENYLIST: Map<Addr, Empty> = Map::new("denylist");
+/// Intermediate ibc-hooks addresses mapped to the remote account they act for, anything
+/// paid to one of them is sent back to that account
+pub const REMOTE_ACCOUNTS: Map<Addr, RemoteAccount> = Map::new("remote_accounts");
+/// IBC payouts of the current transaction waiting for their packet sequence, oldest first
+pub const SENDING_IBC_PAYOUTS: Item<Vec<IbcPayout>> = Item::new("sending_ibc_payouts");
+/// IBC payouts in flight, by channel and packet sequence
+pub const IBC_PAYOUTS: Map<(&str, u64), IbcPayout> = Map::new("ibc_payouts");
+/// Failed IBC payouts, by winner and denom
+pub const FAILED_PAYOUTS: Map<(Addr, &str), Uint128> = Map::new("failed_payouts");
@@ -1,21 +1,59 @@
+use bech32::{ToBase32, Variant};
 use cosmwasm_std::{
-    coin,
-    testing::{mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage},
-    to_binary, CosmosMsg, HexBinary, OwnedDeps, StdResult, Timestamp, Uint128, WasmMsg,
+    coin, from_binary,
+    testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+        MOCK_CONTRACT_ADDR,
+    },
+    to_binary, to_vec, Addr, Attribute, BankMsg, Binary, Coin, ContractResult, CosmosMsg,
+    DistributionMsg, FullDelegation, HexBinary, OwnedDeps, Reply, ReplyOn, StakingMsg, StdResult,
+    SubMsgResponse, SubMsgResult, SystemError, SystemResult, Timestamp, Uint128, WasmMsg,
+    WasmQuery,
 };
 use cw_utils::{Duration, Expiration};
+use k256::ecdsa::{signature::Signer, Signature, SigningKey};
 use nois::{ints_in_range, NoisCallback, ProxyExecuteMsg};
+use proptest::prelude::*;
 
 use crate::{
     contract::{
-        buy_tickets, check_winner, get_config, get_current_draw, get_draw, get_tickets,
-        instantiate, receive_randomness, request_randomness,
+        apply_config_change, buy_tickets, cancel_config_change, check_winner, claim_prize,
+        claim_referral_rewards, claim_refund, claim_unbonded, deposit, fund_loyalty_reserve,
+        fund_pot, fund_promo_budget, fund_randomness_fees, fund_reserve, get_config,
+        get_config_history, get_current_draw, get_deposit, get_draw, get_failed_payouts,
+        get_gaming_limits, get_jackpot, get_loyalty_points, get_loyalty_reserve, get_nft_tickets,
+        get_pending_config, get_promo_budget, get_purchases, get_randomness_fees,
+        get_referral_stats, get_refund, get_reserve, get_schedule, get_second_chance_winners,
+        get_solvency, get_sponsors, get_tickets, ibc_buy_tickets, instantiate, is_allowed, migrate,
+        mint_tickets, propose_config_change, raffle, raffle_second_chance, receive_randomness,
+        recover_surplus, redeem_points, reply, request_randomness, request_second_chance,
+        retry_payouts, self_exclude, set_gaming_limits, sudo, transfer_tickets,
+        update_address_list, update_config, withdraw,
     },
     error::ContractError,
-    helpers::{calculate_matches, calculate_prize_distribution, create_next_draw},
-    state::{Draw, DRAWS},
+    helpers::{
+        calculate_matches, calculate_tickets_prize, calculate_tier_distribution, create_next_draw,
+        derive_intermediate_sender, distribute_overflow, ensure_prize_tiers_are_valid, next_slot,
+        record_spend, update_prize_per_match,
+    },
+    ibc::transfer_msg,
+    pricing::{ensure_pricing_is_valid, quote_tickets},
+    state::{
+        Allowlist, Bundle, ConfigChange, DiscountWindow, Draw, JackpotCap, KeeperBounty,
+        LoyaltyConfig, LoyaltyPoints, MinParticipation, NoLossConfig, OverflowPolicy, Pricing,
+        PrizeTier, Purchase, SecondChanceConfig, SecondChanceStatus, SecondChanceWinner,
+        Sponsorship, TicketResult, UnderSubscribedPolicy, VolumeTier, ALLOWLIST, DENYLIST, DRAWS,
+        LIABILITIES, RANDOMNESS_FEES,
+    },
+};
+use crate::{
+    msg::{
+        Cw4QueryMsg, Cw721ExecuteMsg, IbcLifecycleComplete, InstantiateMsg, MemberResponse,
+        MigrateMsg, NoisPricesResponse, OwnerOfResponse, SudoMsg, TicketMetadata, UpdateConfigMsg,
+        Voucher, VoucherPayload,
+    },
+    state::Status,
 };
-use crate::{msg::InstantiateMsg, state::Status};
 
 const ADMIN_ADDR: &str = "admin";
 const PARTICIPANT_ADDR: &str = "participant";
@@ -23,27 +61,82 @@ const NOIS_ADDR: &str = "nois";
 const DENOM: &str = "udenom";
 const MAX_TICKETS: u32 = 10;
 const TICKET_PRICE: u128 = 1000;
+const NFT_ADDR: &str = "ticket_nft";
+
+fn mock_nois_prices(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, prices: Vec<Coin>) {
+    deps.querier.update_wasm(move |query| match query {
+        WasmQuery::Smart { contract_addr, .. } if contract_addr == NOIS_ADDR => {
+            SystemResult::Ok(ContractResult::Ok(
+                to_binary(&NoisPricesResponse {
+                    prices: prices.clone(),
+                })
+                .unwrap(),
+            ))
+        }
+        _ => SystemResult::Err(SystemError::UnsupportedRequest {
+            kind: "wasm".to_string(),
+        }),
+    });
+}
 
-fn do_instantaite() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
-    let mut deps = mock_dependencies();
-    let info = mock_info(ADMIN_ADDR, &[]);
-    let env = mock_env();
-
-    let instantiate_msg = InstantiateMsg {
+fn instantiate_msg() -> InstantiateMsg {
+    InstantiateMsg {
         draw_interval: Duration::Time(60),
         max_tickets_per_user: MAX_TICKETS,
         request_timeout: Duration::Time(60),
         nois_proxy: NOIS_ADDR.to_string(),
-        percentage_per_match: [3, 6, 8, 15, 25, 40],
+        percentage_per_match: Some([3, 6, 8, 15, 25, 40]),
         ticket_price: coin(TICKET_PRICE, DENOM),
         treasury_fee: 3,
-    };
+        min_jackpot: None,
+        jackpot_cap: None,
+        prize_tiers: None,
+        no_loss: None,
+        config_timelock: None,
+        pricing: None,
+        referral_bps: None,
+        voucher_pubkey: None,
+        loyalty: None,
+        second_chance: None,
+        progressive_jackpot_bps: None,
+        ticket_nft: None,
+        allowlist: None,
+        max_tickets_per_draw: None,
+        min_participation: None,
+        schedule_anchor: None,
+        keeper_bounty: None,
+        randomness_fee_bps: None,
+        auto_raffle_max_tickets: None,
+    }
+}
+
+fn do_instantaite() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+    let mut deps = mock_dependencies();
+    mock_nois_prices(&mut deps, vec![]);
+    let info = mock_info(ADMIN_ADDR, &[]);
+    let env = mock_env();
 
-    instantiate(deps.as_mut(), env, info, instantiate_msg).unwrap();
+    instantiate(deps.as_mut(), env, info, instantiate_msg()).unwrap();
 
     deps
 }
 
+/// Stands in for the Nois callback, leaving the draw ready to be raffled
+fn set_winner_number(
+    deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+    draw_id: u64,
+    winner_number: &str,
+) {
+    DRAWS
+        .update(deps.as_mut().storage, draw_id, |d| -> StdResult<Draw> {
+            let mut draw = d.unwrap();
+            draw.status = Status::Raffling;
+            draw.winner_number = Some(winner_number.to_string());
+            Ok(draw)
+        })
+        .unwrap();
+}
+
 #[test]
 fn cannot_buy_tickets_when_draw_is_not_open() {
     let mut deps = do_instantaite();
@@ -62,6 +155,8 @@ fn cannot_buy_tickets_when_draw_is_not_open() {
         mock_info(PARTICIPANT_ADDR, &[]),
         vec!["".to_string()],
         1,
+        None,
+        None,
     )
     .unwrap_err();
 
@@ -86,6 +181,8 @@ fn cannot_buy_tickets_when_draw_is_expired() {
         mock_info(PARTICIPANT_ADDR, &[]),
         vec!["".to_string()],
         1,
+        None,
+        None,
     )
     .unwrap_err();
 
@@ -102,6 +199,8 @@ fn cannot_buy_tickets_than_max_tickets_per_user() {
         mock_info(PARTICIPANT_ADDR, &[]),
         vec!["".to_string(); MAX_TICKETS as usize + 1],
         1,
+        None,
+        None,
     )
     .unwrap_err();
 
@@ -118,6 +217,8 @@ fn cannot_buy_tickets_when_sending_invalid_tickets() {
         mock_info(PARTICIPANT_ADDR, &[]),
         vec!["invalid".to_string()],
         1,
+        None,
+        None,
     )
     .unwrap_err();
 
@@ -129,6 +230,8 @@ fn cannot_buy_tickets_when_sending_invalid_tickets() {
         mock_info(PARTICIPANT_ADDR, &[]),
         vec!["1234".to_string()],
         1,
+        None,
+        None,
     )
     .unwrap_err();
 
@@ -145,6 +248,8 @@ fn cannot_buy_tickets_when_not_provide_enough_funds() {
         mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, "other")]),
         vec!["123456".to_string()],
         1,
+        None,
+        None,
     )
     .unwrap_err();
 
@@ -156,12 +261,67 @@ fn cannot_buy_tickets_when_not_provide_enough_funds() {
         mock_info(PARTICIPANT_ADDR, &[coin(0, DENOM)]),
         vec!["123456".to_string()],
         1,
+        None,
+        None,
     )
     .unwrap_err();
 
     assert_eq!(err, ContractError::InsufficientFunds);
 }
 
+#[test]
+fn migrate_should_rebuild_the_ledger_of_unclaimed_prizes() {
+    let mut deps = do_instantaite();
+
+    for (buyer, ticket) in [(PARTICIPANT_ADDR, "123456"), ("other", "123450")] {
+        buy_tickets(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(buyer, &[coin(TICKET_PRICE, DENOM)]),
+            vec![ticket.to_string()],
+            1,
+            None,
+            None,
+        )
+        .unwrap();
+    }
+
+    set_winner_number(&mut deps, 1, "123456");
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    claim_prize(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let liabilities = get_solvency(deps.as_ref(), mock_env()).unwrap().liabilities;
+    assert!(!liabilities.unclaimed_prizes.is_zero());
+
+    // A contract deployed before the ledger existed
+    LIABILITIES.remove(deps.as_mut().storage);
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+    assert_eq!(
+        get_solvency(deps.as_ref(), mock_env()).unwrap().liabilities,
+        liabilities
+    );
+
+    claim_prize(deps.as_mut(), mock_env(), mock_info("other", &[]), 1, None).unwrap();
+}
+
 #[test]
 fn once_purchased_ticktes_should_be_able_to_query_them() {
     let mut deps = do_instantaite();
@@ -173,6 +333,8 @@ fn once_purchased_ticktes_should_be_able_to_query_them() {
         mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
         tickets.clone(),
         1,
+        None,
+        None,
     )
     .unwrap();
 
@@ -198,6 +360,8 @@ fn once_purchased_ticktes_it_should_update_prizes() {
         mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
         tickets.clone(),
         1,
+        None,
+        None,
     )
     .unwrap();
 
@@ -205,7 +369,7 @@ fn once_purchased_ticktes_it_should_update_prizes() {
     let config = get_config(deps.as_ref()).unwrap();
 
     let expected_prize_per_match =
-        calculate_prize_distribution(draw.total_prize.amount.clone(), config.percentage_per_match);
+        calculate_tier_distribution(draw.total_prize.amount, config.tiers(), None).unwrap();
 
     assert_eq!(draw.total_tickets, 1);
     assert_eq!(
@@ -278,6 +442,7 @@ fn only_nois_can_execute_receive_randomness() {
 
     let err = receive_randomness(
         deps.as_mut(),
+        mock_env(),
         mock_info(PARTICIPANT_ADDR, &[]),
         NoisCallback {
             job_id: "1".to_string(),
@@ -310,6 +475,8 @@ fn execute_receive_randomness_should_work() {
         mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
         tickets.clone(),
         1,
+        None,
+        None,
     )
     .unwrap();
 
@@ -323,6 +490,7 @@ fn execute_receive_randomness_should_work() {
 
     receive_randomness(
         deps.as_mut(),
+        mock_env(),
         mock_info("nois", &[]),
         NoisCallback {
             job_id: "1".to_string(),
@@ -378,3 +546,3167 @@ fn check_winner_when_empty() {
     let result = check_winner(deps.as_ref(), "addr".to_string(), 1).unwrap();
     assert_eq!(result, vec![]);
 }
+
+fn update_config_msg() -> UpdateConfigMsg {
+    UpdateConfigMsg {
+        treasury_fee: None,
+        owner: None,
+        ticket_price: None,
+        interval: None,
+        request_timeout: None,
+        nois_proxy: None,
+        max_tickets_per_user: None,
+        percentage_per_match: None,
+        min_jackpot: None,
+        jackpot_cap: None,
+        clear_min_jackpot: false,
+        clear_jackpot_cap: false,
+        prize_tiers: None,
+        config_timelock: None,
+        paused: None,
+        pricing: None,
+        referral_bps: None,
+        voucher_pubkey: None,
+        loyalty: None,
+        second_chance: None,
+        progressive_jackpot_bps: None,
+        ticket_nft: None,
+        allowlist: None,
+        max_tickets_per_draw: None,
+        min_participation: None,
+        schedule_anchor: None,
+        keeper_bounty: None,
+        randomness_fee_bps: None,
+        auto_raffle_max_tickets: None,
+    }
+}
+
+#[test]
+fn fund_pot_should_increase_prize_and_track_sponsor() {
+    let mut deps = do_instantaite();
+
+    let err = fund_pot(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("sponsor", &[coin(5000, "other")]),
+        1,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidCoin);
+
+    fund_pot(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("sponsor", &[coin(5000, DENOM)]),
+        1,
+    )
+    .unwrap();
+
+    fund_pot(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("sponsor", &[coin(1000, DENOM)]),
+        1,
+    )
+    .unwrap();
+
+    let draw = get_current_draw(deps.as_ref()).unwrap();
+
+    assert_eq!(draw.total_prize, coin(6000, DENOM));
+    assert_eq!(
+        draw.prize_per_match,
+        Some(
+            calculate_tier_distribution(
+                Uint128::from(6000u128),
+                get_config(deps.as_ref()).unwrap().tiers(),
+                None
+            )
+            .unwrap()
+        )
+    );
+    assert_eq!(
+        get_sponsors(deps.as_ref(), 1).unwrap(),
+        vec![Sponsorship {
+            sponsor: Addr::unchecked("sponsor"),
+            amount: Uint128::from(6000u128),
+        }]
+    );
+}
+
+#[test]
+fn min_jackpot_is_guaranteed_from_reserve() {
+    let mut deps = do_instantaite();
+
+    let err = fund_reserve(
+        deps.as_mut(),
+        mock_info(PARTICIPANT_ADDR, &[coin(500, DENOM)]),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::Unauthorized);
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            min_jackpot: Some(Uint128::from(10_000u128)),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    fund_reserve(deps.as_mut(), mock_info(ADMIN_ADDR, &[coin(50_000, DENOM)])).unwrap();
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let draw = get_current_draw(deps.as_ref()).unwrap();
+
+    assert_eq!(draw.jackpot_subsidy, Uint128::from(9_600u128));
+    assert_eq!(draw.prize_per_match.unwrap()[5], Uint128::from(10_000u128));
+
+    // Nobody hits the jackpot, so the subsidy stays in the reserve
+    set_winner_number(&mut deps, 1, "999999");
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(get_reserve(deps.as_ref()).unwrap(), coin(50_000, DENOM));
+
+    let next_draw = get_current_draw(deps.as_ref()).unwrap();
+
+    assert_eq!(next_draw.id, 2);
+    assert_eq!(next_draw.total_prize, coin(971, DENOM));
+    assert_eq!(
+        next_draw.prize_per_match.unwrap()[5],
+        Uint128::from(10_000u128)
+    );
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            clear_min_jackpot: true,
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(get_config(deps.as_ref()).unwrap().min_jackpot, None);
+}
+
+#[test]
+fn jackpot_cap_overflow_goes_to_reserve() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            jackpot_cap: Some(JackpotCap {
+                amount: Uint128::from(1_000u128),
+                overflow: OverflowPolicy::Reserve,
+            }),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    fund_pot(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("sponsor", &[coin(10_000, DENOM)]),
+        1,
+    )
+    .unwrap();
+
+    let draw = get_current_draw(deps.as_ref()).unwrap();
+
+    assert_eq!(draw.jackpot_overflow, Uint128::from(3_000u128));
+    assert_eq!(draw.prize_per_match.unwrap()[5], Uint128::from(1_000u128));
+
+    set_winner_number(&mut deps, 1, "999999");
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(get_reserve(deps.as_ref()).unwrap(), coin(3_000, DENOM));
+    assert_eq!(
+        get_current_draw(deps.as_ref()).unwrap().total_prize,
+        coin(6_799, DENOM)
+    );
+}
+
+#[test]
+fn jackpot_cap_does_not_apply_to_a_fixed_top_tier() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            prize_tiers: Some([
+                PrizeTier::Share(1_000),
+                PrizeTier::Share(1_000),
+                PrizeTier::Share(1_000),
+                PrizeTier::Share(1_000),
+                PrizeTier::Share(6_000),
+                PrizeTier::Fixed(Uint128::from(5_000u128)),
+            ]),
+            jackpot_cap: Some(JackpotCap {
+                amount: Uint128::from(1_000u128),
+                overflow: OverflowPolicy::Reserve,
+            }),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let config = get_config(deps.as_ref()).unwrap();
+    let mut draw = get_current_draw(deps.as_ref()).unwrap();
+    draw.total_prize = coin(100_000, DENOM);
+    draw.winners_per_match = Some([0, 0, 0, 0, 0, 2]);
+
+    update_prize_per_match(deps.as_ref().storage, &config, &mut draw).unwrap();
+
+    assert_eq!(draw.jackpot_overflow, Uint128::zero());
+    assert_eq!(draw.prize_per_match.unwrap()[5], Uint128::from(10_000u128));
+}
+
+#[test]
+fn distribute_overflow_only_rewards_tiers_with_winners() {
+    let mut prize_per_match = [Uint128::from(100u128); 6];
+
+    let distributed = distribute_overflow(
+        &mut prize_per_match,
+        [0, 1, 0, 2, 0, 0],
+        Uint128::from(1_000u128),
+        [3, 6, 8, 15, 25, 40].map(|p| PrizeTier::Share(p * 100)),
+    )
+    .unwrap();
+
+    assert_eq!(distributed, Uint128::from(999u128));
+    assert_eq!(prize_per_match[0], Uint128::from(100u128));
+    assert_eq!(prize_per_match[1], Uint128::from(385u128));
+    assert_eq!(prize_per_match[3], Uint128::from(814u128));
+    assert_eq!(prize_per_match[5], Uint128::from(100u128));
+}
+
+#[test]
+fn fixed_tiers_are_paid_before_shared_tiers() {
+    let tiers = [
+        PrizeTier::Fixed(Uint128::from(2_000u128)),
+        PrizeTier::Fixed(Uint128::from(5_000u128)),
+        PrizeTier::Share(1_000),
+        PrizeTier::Share(2_000),
+        PrizeTier::Share(3_000),
+        PrizeTier::Share(4_000),
+    ];
+
+    assert_eq!(
+        calculate_tier_distribution(Uint128::from(100_000u128), tiers, Some([3, 1, 0, 0, 1, 0]))
+            .unwrap(),
+        [6_000u128, 5_000, 8_900, 17_800, 26_700, 35_600].map(Uint128::from)
+    );
+
+    // Not enough to cover every fixed winner, the pot is shared pro-rata between them
+    assert_eq!(
+        calculate_tier_distribution(Uint128::from(5_500u128), tiers, Some([3, 1, 0, 0, 1, 0]))
+            .unwrap(),
+        [3_000u128, 2_500, 0, 0, 0, 0].map(Uint128::from)
+    );
+}
+
+#[test]
+fn prize_tiers_should_replace_percentage_per_match() {
+    let mut deps = mock_dependencies();
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        InstantiateMsg {
+            percentage_per_match: None,
+            ..instantiate_msg()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidPrizeTiers);
+
+    instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        InstantiateMsg {
+            percentage_per_match: None,
+            prize_tiers: Some([
+                PrizeTier::Share(500),
+                PrizeTier::Share(500),
+                PrizeTier::Share(1_000),
+                PrizeTier::Share(2_000),
+                PrizeTier::Share(6_000),
+                PrizeTier::Fixed(Uint128::from(5_000u128)),
+            ]),
+            ..instantiate_msg()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_config(deps.as_ref()).unwrap().percentage_per_match,
+        [5, 5, 10, 20, 60, 0]
+    );
+
+    // Percentages take the tiers over again
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            percentage_per_match: Some([10, 10, 10, 10, 10, 50]),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let config = get_config(deps.as_ref()).unwrap();
+    assert_eq!(config.prize_tiers, None);
+    assert_eq!(config.tiers()[5], PrizeTier::Share(5_000));
+}
+
+#[test]
+fn cannot_configure_shared_tiers_above_the_whole_pot() {
+    let mut deps = do_instantaite();
+
+    let err = update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            prize_tiers: Some([PrizeTier::Share(5_000); 6]),
+            ..update_config_msg()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidPrizeTiers);
+
+    let err = update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            percentage_per_match: Some([50; 6]),
+            ..update_config_msg()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidPrizeTiers);
+
+    // The tiers come from either field, never both
+    let err = update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            percentage_per_match: Some([10; 6]),
+            prize_tiers: Some([PrizeTier::Share(1_000); 6]),
+            ..update_config_msg()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidPrizeTiers);
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        InstantiateMsg {
+            percentage_per_match: Some([50; 6]),
+            ..instantiate_msg()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidPrizeTiers);
+}
+
+fn prize_tier_strategy() -> impl Strategy<Value = PrizeTier> {
+    prop_oneof![
+        any::<u128>().prop_map(|amount| PrizeTier::Fixed(Uint128::from(amount))),
+        any::<u16>().prop_map(PrizeTier::Share),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn tier_distribution_never_panics(
+        total in any::<u128>(),
+        tiers in prop::array::uniform6(prize_tier_strategy()),
+        winners in prop::option::of(prop::array::uniform6(any::<u64>())),
+    ) {
+        let result = calculate_tier_distribution(Uint128::from(total), tiers, winners);
+
+        // With valid tiers and known winners the pot can never be over allocated
+        if let (Ok(prize_per_match), Some(_), Ok(())) =
+            (result, winners, ensure_prize_tiers_are_valid(&tiers))
+        {
+            let allocated = prize_per_match
+                .iter()
+                .try_fold(Uint128::zero(), |acc, p| acc.checked_add(*p));
+            prop_assert!(allocated.unwrap() <= Uint128::from(total));
+        }
+    }
+
+    #[test]
+    fn overflow_distribution_never_panics(
+        prize_per_match in prop::array::uniform6(any::<u128>()),
+        winners in prop::array::uniform6(any::<u64>()),
+        overflow in any::<u128>(),
+        tiers in prop::array::uniform6(prize_tier_strategy()),
+    ) {
+        let mut prize_per_match = prize_per_match.map(Uint128::from);
+
+        if let Ok(distributed) =
+            distribute_overflow(&mut prize_per_match, winners, Uint128::from(overflow), tiers)
+        {
+            prop_assert!(distributed <= Uint128::from(overflow));
+        }
+    }
+
+    #[test]
+    fn tickets_prize_never_panics(
+        prize_per_match in prop::array::uniform6(any::<u128>()),
+        winners in prop::array::uniform6(any::<u64>()),
+        matches in prop::collection::vec(0u8..=6, 0..20),
+    ) {
+        let tickets = matches
+            .into_iter()
+            .map(|matches| TicketResult {
+                ticket_number: "123456".to_string(),
+                matches,
+            })
+            .collect();
+
+        let _ = calculate_tickets_prize(
+            tickets,
+            prize_per_match.map(Uint128::from),
+            winners,
+            DENOM.to_string(),
+        );
+    }
+}
+
+#[test]
+fn claiming_a_tier_without_winners_returns_an_error() {
+    let err = calculate_tickets_prize(
+        vec![TicketResult {
+            ticket_number: "123456".to_string(),
+            matches: 6,
+        }],
+        [Uint128::from(100u128); 6],
+        [0; 6],
+        DENOM.to_string(),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err, ContractError::DividedByZero(_)));
+}
+
+#[test]
+fn raffle_accounts_for_every_coin_of_the_pot() {
+    let mut deps = do_instantaite();
+
+    let purchases = [
+        ("player1", vec!["123456", "123000", "100000"]),
+        ("player2", vec!["120000", "123999"]),
+        ("player3", vec!["120001", "999999"]),
+        ("player4", vec!["777777"]),
+    ];
+
+    for (player, tickets) in purchases.iter() {
+        buy_tickets(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(player, &[coin(TICKET_PRICE * tickets.len() as u128, DENOM)]),
+            tickets.iter().map(|t| t.to_string()).collect(),
+            1,
+            None,
+            None,
+        )
+        .unwrap();
+    }
+
+    set_winner_number(&mut deps, 1, "123456");
+
+    let resp = raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let fee = match &resp.messages[0].msg {
+        CosmosMsg::Bank(BankMsg::Send { amount, .. }) => amount[0].amount,
+        _ => panic!("expected the treasury fee"),
+    };
+
+    let payouts = purchases
+        .iter()
+        .filter_map(|(player, _)| {
+            claim_prize(deps.as_mut(), mock_env(), mock_info(player, &[]), 1, None)
+                .ok()
+                .map(|resp| match &resp.messages[0].msg {
+                    CosmosMsg::Bank(BankMsg::Send { amount, .. }) => amount[0].amount,
+                    _ => panic!("expected the prize"),
+                })
+        })
+        .fold(Uint128::zero(), |acc, p| acc + p);
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    let rollover = get_current_draw(deps.as_ref()).unwrap().total_prize.amount;
+
+    assert!(!draw.dust.is_zero());
+    assert_eq!(draw.total_prize.amount, payouts + fee + rollover);
+}
+
+#[test]
+fn cannot_buy_tickets_when_overpaying() {
+    let mut deps = do_instantaite();
+
+    // Overpaying would leave funds nobody is owed
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE + 1, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::ExcessFunds);
+}
+
+#[test]
+fn solvency_tracks_liabilities_and_only_surplus_can_be_recovered() {
+    let mut deps = do_instantaite();
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE * 2, DENOM)]),
+        vec!["123456".to_string(), "100000".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    fund_reserve(deps.as_mut(), mock_info(ADMIN_ADDR, &[coin(500, DENOM)])).unwrap();
+
+    // Someone sent 300 straight to the contract by mistake
+    deps.querier.update_balance(
+        MOCK_CONTRACT_ADDR,
+        vec![coin(TICKET_PRICE * 2 + 800, DENOM)],
+    );
+
+    let solvency = get_solvency(deps.as_ref(), mock_env()).unwrap();
+
+    assert_eq!(
+        solvency.liabilities.open_pots,
+        Uint128::from(TICKET_PRICE * 2)
+    );
+    assert_eq!(solvency.reserve, Uint128::from(500u128));
+    assert_eq!(solvency.surplus, Uint128::from(300u128));
+    assert_eq!(solvency.deficit, Uint128::zero());
+
+    set_winner_number(&mut deps, 1, "123456");
+
+    let resp = raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let fee = match &resp.messages[0].msg {
+        CosmosMsg::Bank(BankMsg::Send { amount, .. }) => amount[0].amount,
+        _ => panic!("expected the treasury fee"),
+    };
+
+    deps.querier.update_balance(
+        MOCK_CONTRACT_ADDR,
+        vec![coin(TICKET_PRICE * 2 + 800 - fee.u128(), DENOM)],
+    );
+
+    let solvency = get_solvency(deps.as_ref(), mock_env()).unwrap();
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    let next_draw = get_current_draw(deps.as_ref()).unwrap();
+
+    assert_eq!(
+        solvency.liabilities.unclaimed_prizes + solvency.liabilities.open_pots + fee,
+        draw.total_prize.amount
+    );
+    assert_eq!(solvency.liabilities.open_pots, next_draw.total_prize.amount);
+    assert_eq!(solvency.surplus, Uint128::from(300u128));
+
+    let err = recover_surplus(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        Uint128::from(300u128),
+        None,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::Unauthorized);
+
+    let err = recover_surplus(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        Uint128::from(301u128),
+        None,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InsufficientSurplus);
+
+    let resp = recover_surplus(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        Uint128::from(300u128),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: ADMIN_ADDR.to_string(),
+            amount: vec![coin(300, DENOM)],
+        })
+    );
+}
+
+#[test]
+fn no_loss_draw_should_pay_staking_rewards_to_a_weighted_depositor() {
+    let mut deps = mock_dependencies();
+    mock_nois_prices(&mut deps, vec![]);
+
+    let no_loss_msg = || InstantiateMsg {
+        no_loss: Some(NoLossConfig {
+            validators: vec!["val1".to_string(), "val2".to_string()],
+            unbonding_period: 100,
+        }),
+        ..instantiate_msg()
+    };
+
+    // Deposits are staked, so tickets must be priced in the bond denom
+    deps.querier.update_staking("ustake", &[], &[]);
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        no_loss_msg(),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidCoin);
+
+    deps.querier.update_staking(DENOM, &[], &[]);
+    instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        no_loss_msg(),
+    )
+    .unwrap();
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidGameMode);
+
+    let mut env = mock_env();
+    let start = env.block.time;
+
+    let resp = deposit(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("alice", &[coin(1000, DENOM)]),
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Staking(StakingMsg::Delegate {
+            validator: "val1".to_string(),
+            amount: coin(1000, DENOM),
+        })
+    );
+
+    env.block.time = start.plus_seconds(30);
+
+    let resp = deposit(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("bob", &[coin(3000, DENOM)]),
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Staking(StakingMsg::Delegate {
+            validator: "val2".to_string(),
+            amount: coin(3000, DENOM),
+        })
+    );
+
+    let alice = get_deposit(deps.as_ref(), env.clone(), "alice".to_string()).unwrap();
+    assert_eq!(alice.weight, Uint128::from(30_000u128));
+
+    // Weights are clamped to the end of the draw
+    env.block.time = start.plus_seconds(61);
+
+    let alice = get_deposit(deps.as_ref(), env.clone(), "alice".to_string()).unwrap();
+    let bob = get_deposit(deps.as_ref(), env.clone(), "bob".to_string()).unwrap();
+    assert_eq!(alice.weight, Uint128::from(60_000u128));
+    assert_eq!(bob.weight, Uint128::from(90_000u128));
+
+    let delegation = |validator: &str, amount: u128| FullDelegation {
+        delegator: Addr::unchecked(MOCK_CONTRACT_ADDR),
+        validator: validator.to_string(),
+        amount: coin(amount, DENOM),
+        can_redelegate: coin(amount, DENOM),
+        accumulated_rewards: vec![coin(100, DENOM)],
+    };
+
+    deps.querier.update_staking(
+        DENOM,
+        &[],
+        &[delegation("val1", 1000), delegation("val2", 3000)],
+    );
+
+    let resp = request_randomness(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Distribution(DistributionMsg::WithdrawDelegatorReward {
+            validator: "val1".to_string(),
+        })
+    );
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.total_prize, coin(200, DENOM));
+
+    receive_randomness(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(NOIS_ADDR, &[]),
+        NoisCallback {
+            job_id: "1".to_string(),
+            randomness: HexBinary::from([0x42; 32]),
+        },
+    )
+    .unwrap();
+
+    // Depositors are gone through in pages, the prize is paid after the last one
+    let resp = raffle(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        Some(1),
+    )
+    .unwrap();
+    assert!(resp.messages.is_empty());
+    assert_eq!(
+        get_draw(deps.as_ref(), 1).unwrap().unwrap().status,
+        Status::Raffling
+    );
+
+    let resp = raffle(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let winner = match &resp.messages[0].msg {
+        CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => {
+            assert_eq!(amount, &vec![coin(194, DENOM)]);
+            to_address.clone()
+        }
+        _ => panic!("expected the prize"),
+    };
+
+    assert!(winner == "alice" || winner == "bob");
+    assert_eq!(
+        resp.messages[1].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: ADMIN_ADDR.to_string(),
+            amount: vec![coin(6, DENOM)],
+        })
+    );
+
+    let next_draw = get_current_draw(deps.as_ref()).unwrap();
+    assert_eq!(next_draw.id, 2);
+    assert_eq!(next_draw.total_prize.amount, Uint128::zero());
+
+    let err = withdraw(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("bob", &[]),
+        Uint128::from(3001u128),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InsufficientFunds);
+
+    let resp = withdraw(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("bob", &[]),
+        Uint128::from(3000u128),
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Staking(StakingMsg::Undelegate {
+            validator: "val2".to_string(),
+            amount: coin(3000, DENOM),
+        })
+    );
+
+    // Rewards paid out by the undelegation belong to the new draw
+    let next_draw = get_current_draw(deps.as_ref()).unwrap();
+    assert_eq!(next_draw.total_prize.amount, Uint128::from(100u128));
+
+    let bob = get_deposit(deps.as_ref(), env.clone(), "bob".to_string()).unwrap();
+    assert_eq!(bob.principal, Uint128::zero());
+    assert_eq!(bob.unbonding.len(), 1);
+
+    let err = claim_unbonded(deps.as_mut(), env.clone(), mock_info("bob", &[])).unwrap_err();
+    assert_eq!(err, ContractError::NothingUnbonded);
+
+    // The principal on its way back makes up for the withdrawal it is owed to
+    deps.querier
+        .update_balance(MOCK_CONTRACT_ADDR, vec![coin(100, DENOM)]);
+    let solvency = get_solvency(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(solvency.unbonding, Uint128::from(3000u128));
+    assert_eq!(solvency.deficit, Uint128::zero());
+
+    env.block.time = env.block.time.plus_seconds(100);
+
+    let resp = claim_unbonded(deps.as_mut(), env.clone(), mock_info("bob", &[])).unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "bob".to_string(),
+            amount: vec![coin(3000, DENOM)],
+        })
+    );
+
+    let solvency = get_solvency(deps.as_ref(), env).unwrap();
+    assert_eq!(solvency.liabilities.pending_withdrawals, Uint128::zero());
+}
+
+#[test]
+fn config_changes_should_wait_for_the_timelock() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            config_timelock: Some(Duration::Time(100)),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let err = update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            treasury_fee: Some(10),
+            ..update_config_msg()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::ConfigChangeIsTimelocked);
+
+    // Pausing is an emergency measure and applies right away
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            paused: Some(true),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::ContractPaused);
+
+    let err = propose_config_change(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        update_config_msg(),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::Unauthorized);
+
+    propose_config_change(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            treasury_fee: Some(10),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let pending = get_pending_config(deps.as_ref()).unwrap().unwrap();
+    assert_eq!(pending.config.treasury_fee, Some(10));
+    assert_eq!(
+        pending.activates_at,
+        Expiration::AtTime(mock_env().block.time.plus_seconds(100))
+    );
+
+    let err =
+        apply_config_change(deps.as_mut(), mock_env(), mock_info(ADMIN_ADDR, &[])).unwrap_err();
+
+    assert_eq!(err, ContractError::ConfigChangeIsTimelocked);
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(100);
+
+    apply_config_change(deps.as_mut(), env.clone(), mock_info(ADMIN_ADDR, &[])).unwrap();
+
+    assert_eq!(get_config(deps.as_ref()).unwrap().treasury_fee, 10);
+    assert_eq!(get_pending_config(deps.as_ref()).unwrap(), None);
+
+    propose_config_change(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            treasury_fee: Some(50),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    cancel_config_change(deps.as_mut(), mock_info(ADMIN_ADDR, &[])).unwrap();
+    assert_eq!(get_pending_config(deps.as_ref()).unwrap(), None);
+
+    let err = cancel_config_change(deps.as_mut(), mock_info(ADMIN_ADDR, &[])).unwrap_err();
+    assert_eq!(err, ContractError::NoPendingConfigChange);
+}
+
+#[test]
+fn config_changes_should_be_recorded_in_history() {
+    let mut deps = do_instantaite();
+
+    let resp = update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            treasury_fee: Some(5),
+            max_tickets_per_user: Some(20),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.events[0].attributes,
+        vec![
+            Attribute::new("treasury_fee", "3 -> 5"),
+            Attribute::new("max_tickets_per_user", "10 -> 20"),
+        ]
+    );
+
+    // Nothing changed, nothing recorded
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            treasury_fee: Some(5),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(10);
+
+    update_config(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            paused: Some(true),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let history = get_config_history(deps.as_ref(), None, None).unwrap();
+
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].version, 1);
+    assert_eq!(history[0].updated_by, Addr::unchecked(ADMIN_ADDR));
+    assert_eq!(history[0].updated_at, mock_env().block.time);
+    assert_eq!(
+        history[0].changes[0],
+        ConfigChange {
+            field: "treasury_fee".to_string(),
+            old: "3".to_string(),
+            new: "5".to_string(),
+        }
+    );
+    assert_eq!(history[1].version, 2);
+    assert_eq!(history[1].updated_at, env.block.time);
+    assert_eq!(history[1].changes[0].field, "paused");
+
+    let history = get_config_history(deps.as_ref(), Some(1), Some(1)).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].version, 2);
+}
+
+fn promo_pricing() -> Pricing {
+    let now = mock_env().block.time;
+
+    Pricing {
+        volume_tiers: vec![VolumeTier {
+            min_tickets: 10,
+            discount_bps: 1000,
+        }],
+        bundle: Some(Bundle { buy: 4, free: 1 }),
+        windows: vec![DiscountWindow {
+            start: now,
+            end: now.plus_seconds(30),
+            discount_bps: 500,
+        }],
+    }
+}
+
+#[test]
+fn quote_tickets_should_apply_bundles_and_discounts() {
+    let price = coin(TICKET_PRICE, DENOM);
+    let pricing = promo_pricing();
+    let now = mock_env().block.time;
+
+    let quote = quote_tickets(&price, None, 10, now).unwrap();
+    assert_eq!(quote.price, coin(10_000, DENOM));
+
+    // Outside the window and below the volume tier
+    let quote = quote_tickets(&price, Some(&pricing), 3, now.plus_seconds(30)).unwrap();
+    assert_eq!(quote.free_tickets, 0);
+    assert_eq!(quote.price, coin(3000, DENOM));
+
+    // Two free tickets, then 10% and 5% off the remaining eight
+    let quote = quote_tickets(&price, Some(&pricing), 10, now).unwrap();
+    assert_eq!(quote.free_tickets, 2);
+    assert_eq!(quote.volume_discount_bps, 1000);
+    assert_eq!(quote.window_discount_bps, 500);
+    assert_eq!(quote.price, coin(6840, DENOM));
+
+    let err = ensure_pricing_is_valid(&Pricing {
+        volume_tiers: vec![VolumeTier {
+            min_tickets: 1,
+            discount_bps: 10_001,
+        }],
+        ..Pricing::default()
+    })
+    .unwrap_err();
+    assert_eq!(err, ContractError::InvalidPricing);
+
+    let err = ensure_pricing_is_valid(&Pricing {
+        bundle: Some(Bundle { buy: 0, free: 1 }),
+        ..Pricing::default()
+    })
+    .unwrap_err();
+    assert_eq!(err, ContractError::InvalidPricing);
+}
+
+#[test]
+fn buy_tickets_should_charge_and_record_the_discounted_price() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            pricing: Some(promo_pricing()),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let tickets = vec!["123456".to_string(); 10];
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(6839, DENOM)]),
+        tickets.clone(),
+        1,
+        None,
+        None,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InsufficientFunds);
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(6840, DENOM)]),
+        tickets,
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.total_tickets, 10);
+    assert_eq!(draw.total_prize, coin(6840, DENOM));
+
+    let purchases = get_purchases(deps.as_ref(), 1, PARTICIPANT_ADDR.to_string()).unwrap();
+    assert_eq!(
+        purchases,
+        vec![Purchase {
+            tickets: 10,
+            free_tickets: 2,
+            paid: coin(6840, DENOM),
+            subsidy: Uint128::zero(),
+            points: 0,
+        }]
+    );
+}
+
+#[test]
+fn cannot_buy_an_empty_list_of_tickets() {
+    let mut deps = do_instantaite();
+
+    // An empty purchase would be priced at nothing
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec![],
+        1,
+        None,
+        None,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::NoTickets);
+}
+
+#[test]
+fn referral_credits_should_only_be_paid_as_far_as_the_treasury_fee_goes() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            referral_bps: Some(5000),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        Some("referrer".to_string()),
+        None,
+    )
+    .unwrap();
+
+    let stats = get_referral_stats(deps.as_ref(), "referrer".to_string()).unwrap();
+    assert_eq!(stats.pending, Uint128::from(500u128));
+
+    // The jackpot is won, so only the tiers without winners pay the treasury fee
+    set_winner_number(&mut deps, 1, "123456");
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.referral_rewards, Uint128::from(500u128));
+    assert_eq!(draw.referral_funded, Uint128::from(17u128));
+
+    let stats = get_referral_stats(deps.as_ref(), "referrer".to_string()).unwrap();
+    assert_eq!(stats.pending, Uint128::zero());
+    assert_eq!(stats.claimable, draw.referral_funded);
+    assert_eq!(stats.stats.total_earned, draw.referral_funded);
+
+    let resp =
+        claim_referral_rewards(deps.as_mut(), mock_env(), mock_info("referrer", &[])).unwrap();
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "referrer".to_string(),
+            amount: vec![coin(draw.referral_funded.u128(), DENOM)],
+        })
+    );
+}
+
+#[test]
+fn referral_rewards_should_be_funded_at_raffle_and_claimable() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            referral_bps: Some(1000),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        Some(PARTICIPANT_ADDR.to_string()),
+        None,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidReferrer);
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE * 2, DENOM)]),
+        vec!["123456".to_string(); 2],
+        1,
+        Some("referrer".to_string()),
+        None,
+    )
+    .unwrap();
+
+    // The first referrer sticks
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        Some("other".to_string()),
+        None,
+    )
+    .unwrap();
+
+    let stats = get_referral_stats(deps.as_ref(), "referrer".to_string()).unwrap();
+    assert_eq!(stats.stats.referred_users, 1);
+    assert_eq!(stats.stats.tickets_sold, 3);
+    assert_eq!(stats.stats.total_earned, Uint128::from(300u128));
+    assert_eq!(stats.pending, Uint128::from(300u128));
+    assert_eq!(stats.claimable, Uint128::zero());
+
+    let stats = get_referral_stats(deps.as_ref(), PARTICIPANT_ADDR.to_string()).unwrap();
+    assert_eq!(stats.referrer, Some(Addr::unchecked("referrer")));
+
+    let err =
+        claim_referral_rewards(deps.as_mut(), mock_env(), mock_info("referrer", &[])).unwrap_err();
+    assert_eq!(err, ContractError::NoReferralRewards);
+
+    set_winner_number(&mut deps, 1, "999999");
+
+    let resp = raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    // The credits exceed the treasury fee, which is used up entirely and scales
+    // them down, the rollover is left alone
+    let attribute = |key: &str| {
+        resp.events[0]
+            .attributes
+            .iter()
+            .find(|a| a.key == key)
+            .unwrap()
+            .value
+            .clone()
+    };
+
+    assert!(resp.messages.is_empty());
+    assert_eq!(attribute("treasury_fee"), "0");
+    assert_eq!(attribute("referral_rewards"), "87");
+    assert_eq!(attribute("rollover"), "2913");
+
+    let stats = get_referral_stats(deps.as_ref(), "referrer".to_string()).unwrap();
+    assert_eq!(stats.stats.total_earned, Uint128::from(87u128));
+    assert_eq!(stats.claimable, Uint128::from(87u128));
+
+    let resp =
+        claim_referral_rewards(deps.as_mut(), mock_env(), mock_info("referrer", &[])).unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "referrer".to_string(),
+            amount: vec![coin(87, DENOM)],
+        })
+    );
+
+    let stats = get_referral_stats(deps.as_ref(), "referrer".to_string()).unwrap();
+    assert_eq!(stats.stats.total_earned, Uint128::from(87u128));
+    assert_eq!(stats.stats.total_claimed, Uint128::from(87u128));
+    assert_eq!(stats.claimable, Uint128::zero());
+
+    let solvency = get_solvency(deps.as_ref(), mock_env()).unwrap();
+    assert_eq!(solvency.liabilities.accrued_fees, Uint128::zero());
+}
+
+fn sign_voucher(
+    key: &SigningKey,
+    nonce: &str,
+    discount_bps: u16,
+    recipient: Option<String>,
+) -> Voucher {
+    let expires_at = mock_env().block.time.plus_seconds(30);
+    let max_subsidy = Uint128::from(TICKET_PRICE);
+
+    let payload = to_vec(&VoucherPayload {
+        contract: MOCK_CONTRACT_ADDR.to_string(),
+        nonce: nonce.to_string(),
+        discount_bps,
+        max_subsidy,
+        expires_at,
+        recipient: recipient.clone(),
+    })
+    .unwrap();
+    let signature: Signature = key.sign(&payload);
+
+    Voucher {
+        nonce: nonce.to_string(),
+        discount_bps,
+        max_subsidy,
+        expires_at,
+        recipient,
+        signature: Binary::from(signature.as_ref()),
+    }
+}
+
+#[test]
+fn vouchers_should_be_subsidised_by_the_promo_budget() {
+    let mut deps = do_instantaite();
+    let key = SigningKey::from_bytes(&[0x42; 32]).unwrap();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            voucher_pubkey: Some(Binary::from(key.verifying_key().to_bytes().as_slice())),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let free_ticket = sign_voucher(&key, "1", 10_000, Some(PARTICIPANT_ADDR.to_string()));
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        Some(free_ticket.clone()),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::PromoBudgetExhausted);
+
+    fund_promo_budget(
+        deps.as_mut(),
+        mock_info(ADMIN_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+    )
+    .unwrap();
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("someone", &[]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        Some(free_ticket.clone()),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidVoucher);
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        Some(Voucher {
+            discount_bps: 5000,
+            ..free_ticket.clone()
+        }),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InvalidVoucher);
+
+    // The voucher only covers a single ticket
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec!["123456".to_string(); 2],
+        1,
+        None,
+        Some(free_ticket.clone()),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InsufficientFunds);
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        Some(free_ticket.clone()),
+    )
+    .unwrap();
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.total_prize, coin(TICKET_PRICE, DENOM));
+    assert_eq!(get_promo_budget(deps.as_ref()).unwrap(), coin(0, DENOM));
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        Some(free_ticket),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::VoucherAlreadyUsed);
+
+    // Anyone may redeem an unbound voucher before it expires
+    let half_off = sign_voucher(&key, "2", 5000, None);
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(30);
+
+    let err = buy_tickets(
+        deps.as_mut(),
+        env,
+        mock_info("someone", &[coin(TICKET_PRICE / 2, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        Some(half_off),
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::VoucherExpired);
+}
+
+#[test]
+fn loyalty_points_should_be_earned_and_redeemed_for_tickets() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            loyalty: Some(LoyaltyConfig {
+                points_per_ticket: 10,
+                points_per_free_ticket: 20,
+            }),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE * 2, DENOM)]),
+        vec!["123456".to_string(); 2],
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let points = get_loyalty_points(deps.as_ref(), PARTICIPANT_ADDR.to_string()).unwrap();
+    assert_eq!(points.balance, 20);
+    assert_eq!(points.lifetime, 20);
+
+    let err = redeem_points(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec!["654321".to_string()],
+        1,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::LoyaltyReserveExhausted);
+
+    fund_loyalty_reserve(
+        deps.as_mut(),
+        mock_info(ADMIN_ADDR, &[coin(TICKET_PRICE * 2, DENOM)]),
+    )
+    .unwrap();
+
+    let err = redeem_points(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec!["654321".to_string(); 2],
+        1,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ContractError::InsufficientPoints);
+
+    redeem_points(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        vec!["654321".to_string()],
+        1,
+    )
+    .unwrap();
+
+    let points = get_loyalty_points(deps.as_ref(), PARTICIPANT_ADDR.to_string()).unwrap();
+    assert_eq!(
+        points,
+        LoyaltyPoints {
+            balance: 0,
+            lifetime: 20,
+            redeemed: 20,
+        }
+    );
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.total_tickets, 3);
+    assert_eq!(draw.total_prize, coin(TICKET_PRICE * 3, DENOM));
+    assert_eq!(
+        get_loyalty_reserve(deps.as_ref()).unwrap(),
+        coin(TICKET_PRICE, DENOM)
+    );
+    assert_eq!(
+        get_tickets(deps.as_ref(), 1, PARTICIPANT_ADDR.to_string()).unwrap(),
+        vec!["123456", "123456", "654321"]
+    );
+}
+
+#[test]
+fn second_chance_should_pay_tickets_without_matches() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            second_chance: Some(SecondChanceConfig {
+                pool_bps: 1000,
+                winners: 3,
+                prize: Some(Uint128::from(100u128)),
+            }),
+            ticket_nft: Some(NFT_ADDR.to_string()),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    for (buyer, ticket) in [
+        ("alice", "000000"),
+        ("bob", "000001"),
+        ("dave", "000002"),
+        ("carol", "999999"),
+    ] {
+        buy_tickets(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(buyer, &[coin(TICKET_PRICE, DENOM)]),
+            vec![ticket.to_string()],
+            1,
+            None,
+            None,
+        )
+        .unwrap();
+    }
+
+    // Dave's ticket ends up in a token that can no longer be looked up
+    mint_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("dave", &[]),
+        1,
+        vec!["000002".to_string()],
+    )
+    .unwrap();
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.total_prize, coin(3600, DENOM));
+    assert_eq!(draw.second_chance_pool, Uint128::from(400u128));
+
+    let err = request_second_chance(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::DrawIsNotClaimable);
+
+    set_winner_number(&mut deps, 1, "999999");
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.second_chance_status, Some(SecondChanceStatus::Pending));
+
+    let resp = request_second_chance(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: NOIS_ADDR.to_string(),
+            msg: to_binary(&ProxyExecuteMsg::GetNextRandomness {
+                job_id: "second-chance/1".to_string(),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+
+    let err = request_second_chance(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::RandomnessAlreadyRequested);
+
+    let next_pot = get_current_draw(deps.as_ref()).unwrap().total_prize.amount;
+
+    let resp = receive_randomness(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(NOIS_ADDR, &[]),
+        NoisCallback {
+            job_id: "second-chance/1".to_string(),
+            randomness: HexBinary::from([0x42; 32]),
+        },
+    )
+    .unwrap();
+    assert!(resp.messages.is_empty());
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(
+        draw.second_chance_status,
+        Some(SecondChanceStatus::Raffling)
+    );
+
+    // Three wallets and a token hold tickets, the first page leaves some for later
+    let resp = raffle_second_chance(deps.as_mut(), mock_env(), 1, Some(2)).unwrap();
+    assert!(resp.messages.is_empty());
+    assert!(get_second_chance_winners(deps.as_ref(), 1)
+        .unwrap()
+        .is_empty());
+
+    // Tickets stay put until the second chance is settled
+    let err = transfer_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("alice", &[]),
+        1,
+        vec!["000000".to_string()],
+        "erin".to_string(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::SecondChanceNotSettled);
+
+    let err = mint_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("bob", &[]),
+        1,
+        vec!["000001".to_string()],
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::SecondChanceNotSettled);
+
+    // Three tickets matched nothing and each wins the fixed prize, the prize of the one
+    // whose owner is unknown goes back to the pot along with what the prizes left
+    let resp = raffle_second_chance(deps.as_mut(), mock_env(), 1, None).unwrap();
+    assert_eq!(resp.messages.len(), 2);
+
+    let mut winners = get_second_chance_winners(deps.as_ref(), 1).unwrap();
+    winners.sort_by(|a, b| a.addr.cmp(&b.addr));
+
+    assert_eq!(
+        winners,
+        vec![
+            SecondChanceWinner {
+                addr: Addr::unchecked("alice"),
+                ticket: "000000".to_string(),
+                prize: coin(100, DENOM),
+            },
+            SecondChanceWinner {
+                addr: Addr::unchecked("bob"),
+                ticket: "000001".to_string(),
+                prize: coin(100, DENOM),
+            },
+        ]
+    );
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.second_chance_status, Some(SecondChanceStatus::Settled));
+    assert_eq!(
+        get_current_draw(deps.as_ref()).unwrap().total_prize.amount,
+        next_pot + Uint128::from(200u128)
+    );
+
+    let err = request_second_chance(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::NoSecondChance);
+}
+
+#[test]
+fn progressive_jackpot_should_build_up_until_six_matches() {
+    let mut deps = do_instantaite();
+
+    // Together the shares taken from sales cannot exceed the whole sale
+    let err = update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            progressive_jackpot_bps: Some(6000),
+            randomness_fee_bps: Some(5000),
+            ..update_config_msg()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::InvalidBps);
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            progressive_jackpot_bps: Some(1000),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    for draw_id in [1, 2] {
+        buy_tickets(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+            vec!["123456".to_string()],
+            draw_id,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let draw = get_draw(deps.as_ref(), draw_id).unwrap().unwrap();
+        let jackpot = get_jackpot(deps.as_ref()).unwrap();
+
+        // Nobody matches all six digits on the first draw, so the jackpot carries over
+        let winner_number = if draw_id == 1 { "654321" } else { "123456" };
+
+        set_winner_number(&mut deps, draw_id, winner_number);
+
+        raffle(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(PARTICIPANT_ADDR, &[]),
+            draw_id,
+            None,
+        )
+        .unwrap();
+
+        let raffled = get_draw(deps.as_ref(), draw_id).unwrap().unwrap();
+
+        if draw_id == 1 {
+            assert_eq!(jackpot, coin(100, DENOM));
+            assert_eq!(raffled.progressive_jackpot, Uint128::zero());
+            assert_eq!(get_jackpot(deps.as_ref()).unwrap(), coin(100, DENOM));
+        } else {
+            assert_eq!(jackpot, coin(200, DENOM));
+            assert_eq!(raffled.progressive_jackpot, Uint128::from(200u128));
+            assert_eq!(
+                raffled.prize_per_match.unwrap()[5],
+                draw.prize_per_match.unwrap()[5]
+                    .checked_add(Uint128::from(200u128))
+                    .unwrap()
+            );
+            assert_eq!(get_jackpot(deps.as_ref()).unwrap(), coin(0, DENOM));
+        }
+    }
+}
+
+#[test]
+fn transfer_tickets_should_move_tickets_until_claimed() {
+    let mut deps = do_instantaite();
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE * 2, DENOM)]),
+        vec!["123456".to_string(), "111111".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let err = transfer_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        vec!["222222".to_string()],
+        "friend".to_string(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::TicketNotOwned);
+
+    transfer_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        vec!["123456".to_string()],
+        "friend".to_string(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_tickets(deps.as_ref(), 1, PARTICIPANT_ADDR.to_string()).unwrap(),
+        vec!["111111".to_string()]
+    );
+    assert_eq!(
+        get_tickets(deps.as_ref(), 1, "friend".to_string()).unwrap(),
+        vec!["123456".to_string()]
+    );
+
+    set_winner_number(&mut deps, 1, "123456");
+
+    let err = transfer_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        vec!["111111".to_string()],
+        "friend".to_string(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::DrawIsNotOpen);
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    // The winning ticket can still change hands before its prize is claimed
+    transfer_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("friend", &[]),
+        1,
+        vec!["123456".to_string()],
+        "wallet".to_string(),
+    )
+    .unwrap();
+
+    claim_prize(deps.as_mut(), mock_env(), mock_info("wallet", &[]), 1, None).unwrap();
+
+    let err = transfer_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("wallet", &[]),
+        1,
+        vec!["123456".to_string()],
+        "friend".to_string(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::PrizeAlreadyClaimed);
+}
+
+#[test]
+fn nft_tickets_should_pay_the_current_owner() {
+    let mut deps = do_instantaite();
+
+    let err = mint_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        vec!["123456".to_string()],
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::NoTicketNft);
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            ticket_nft: Some(NFT_ADDR.to_string()),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE * 2, DENOM)]),
+        vec!["123456".to_string(), "111111".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let resp = mint_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        vec!["123456".to_string()],
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: NFT_ADDR.to_string(),
+            msg: to_binary(&Cw721ExecuteMsg::Mint {
+                token_id: "1".to_string(),
+                owner: PARTICIPANT_ADDR.to_string(),
+                token_uri: None,
+                extension: TicketMetadata {
+                    draw_id: 1,
+                    numbers: vec!["123456".to_string()],
+                },
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+
+    assert_eq!(
+        get_tickets(deps.as_ref(), 1, PARTICIPANT_ADDR.to_string()).unwrap(),
+        vec!["111111".to_string()]
+    );
+    assert_eq!(
+        get_nft_tickets(deps.as_ref(), 1, "1".to_string()).unwrap(),
+        vec!["123456".to_string()]
+    );
+
+    // Minted tickets still count against the buyer's limit
+    let err = buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(
+            PARTICIPANT_ADDR,
+            &[coin(TICKET_PRICE * (MAX_TICKETS as u128 - 1), DENOM)],
+        ),
+        (1..MAX_TICKETS).map(|i| format!("{:06}", i)).collect(),
+        1,
+        None,
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::MaxTicketsPerUserExceeded);
+
+    // The token was sold on a secondary market
+    deps.querier.update_wasm(|query| match query {
+        WasmQuery::Smart { contract_addr, .. } if contract_addr == NFT_ADDR => {
+            SystemResult::Ok(ContractResult::Ok(
+                to_binary(&OwnerOfResponse {
+                    owner: "collector".to_string(),
+                    approvals: vec![],
+                })
+                .unwrap(),
+            ))
+        }
+        _ => SystemResult::Err(SystemError::UnsupportedRequest {
+            kind: "wasm".to_string(),
+        }),
+    });
+
+    set_winner_number(&mut deps, 1, "123456");
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.winners_per_match.unwrap()[5], 1);
+
+    let err = claim_prize(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        Some(vec!["1".to_string()]),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::TicketNotOwned);
+
+    let resp = claim_prize(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("collector", &[]),
+        1,
+        Some(vec!["1".to_string()]),
+    )
+    .unwrap();
+
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "collector".to_string(),
+            amount: vec![coin(draw.prize_per_match.unwrap()[5].u128(), DENOM)],
+        })
+    );
+
+    let err = claim_prize(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("collector", &[]),
+        1,
+        Some(vec!["1".to_string()]),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::PrizeAlreadyClaimed);
+}
+
+#[test]
+fn ibc_buy_tickets_should_send_prizes_back_to_the_remote_sender() {
+    let mut deps = do_instantaite();
+
+    let remote_sender = bech32::encode("osmo", [7u8; 20].to_base32(), Variant::Bech32).unwrap();
+    let intermediate = derive_intermediate_sender("channel-0", &remote_sender, "juno").unwrap();
+
+    // Somebody trying to buy on behalf of a remote account
+    let err = ibc_buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(&intermediate, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        "channel-1".to_string(),
+        remote_sender.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::InvalidRemoteSender);
+
+    ibc_buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(&intermediate, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        "channel-0".to_string(),
+        remote_sender.clone(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_tickets(deps.as_ref(), 1, intermediate.clone()).unwrap(),
+        vec!["123456".to_string()]
+    );
+
+    set_winner_number(&mut deps, 1, "123456");
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+
+    let resp = claim_prize(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(&intermediate, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let prize = coin(draw.prize_per_match.unwrap()[5].u128(), DENOM);
+
+    // The transfer asks ibc-hooks to call back once it is done
+    assert_eq!(resp.messages[0].id, 1);
+    assert_eq!(resp.messages[0].reply_on, ReplyOn::Success);
+    assert_eq!(
+        resp.messages[0].msg,
+        transfer_msg(
+            "channel-0",
+            MOCK_CONTRACT_ADDR,
+            &remote_sender,
+            &prize,
+            mock_env().block.time.plus_seconds(600),
+            &format!(r#"{{"ibc_callback":"{}"}}"#, MOCK_CONTRACT_ADDR),
+        )
+    );
+
+    let transfer_reply = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, sequence| {
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: 1,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: Some(Binary::from(vec![0x08, sequence])),
+                }),
+            },
+        )
+        .unwrap()
+    };
+    let lifecycle = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, complete| {
+        sudo(
+            deps.as_mut(),
+            mock_env(),
+            SudoMsg::IbcLifecycleComplete(complete),
+        )
+        .unwrap()
+    };
+
+    // A timed out transfer is credited back and can be sent again
+    transfer_reply(&mut deps, 7);
+    lifecycle(
+        &mut deps,
+        IbcLifecycleComplete::IbcTimeout {
+            channel: "channel-0".to_string(),
+            sequence: 7,
+        },
+    );
+
+    assert_eq!(
+        get_failed_payouts(deps.as_ref(), intermediate.clone()).unwrap(),
+        vec![prize.clone()]
+    );
+    assert_eq!(
+        get_solvency(deps.as_ref(), mock_env())
+            .unwrap()
+            .liabilities
+            .failed_payouts,
+        prize.amount
+    );
+
+    let resp = retry_payouts(deps.as_mut(), mock_env(), mock_info(&intermediate, &[])).unwrap();
+    assert_eq!(resp.messages[0].reply_on, ReplyOn::Success);
+    assert!(get_failed_payouts(deps.as_ref(), intermediate.clone())
+        .unwrap()
+        .is_empty());
+
+    // Once acknowledged the payout is done with
+    transfer_reply(&mut deps, 8);
+    lifecycle(
+        &mut deps,
+        IbcLifecycleComplete::IbcAck {
+            channel: "channel-0".to_string(),
+            sequence: 8,
+            ack: "AQ==".to_string(),
+            success: true,
+        },
+    );
+
+    assert!(get_failed_payouts(deps.as_ref(), intermediate.clone())
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        retry_payouts(deps.as_mut(), mock_env(), mock_info(&intermediate, &[])).unwrap_err(),
+        ContractError::NoPrizeToClaim
+    );
+}
+
+#[test]
+fn allowlist_and_denylist_should_gate_participants() {
+    let mut deps = do_instantaite();
+
+    assert!(is_allowed(deps.as_ref(), PARTICIPANT_ADDR.to_string()).unwrap());
+
+    let err = update_address_list(
+        deps.as_mut(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        DENYLIST,
+        "denylist",
+        vec![PARTICIPANT_ADDR.to_string()],
+        vec![],
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::Unauthorized);
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            allowlist: Some(Allowlist::Static),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let buy = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, buyer: &str| {
+        buy_tickets(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(buyer, &[coin(TICKET_PRICE, DENOM)]),
+            vec!["123456".to_string()],
+            1,
+            None,
+            None,
+        )
+    };
+
+    assert_eq!(
+        buy(&mut deps, PARTICIPANT_ADDR).unwrap_err(),
+        ContractError::AddressNotAllowed
+    );
+
+    update_address_list(
+        deps.as_mut(),
+        mock_info(ADMIN_ADDR, &[]),
+        ALLOWLIST,
+        "allowlist",
+        vec![PARTICIPANT_ADDR.to_string()],
+        vec![],
+    )
+    .unwrap();
+
+    buy(&mut deps, PARTICIPANT_ADDR).unwrap();
+
+    // The denylist wins over the allowlist
+    update_address_list(
+        deps.as_mut(),
+        mock_info(ADMIN_ADDR, &[]),
+        DENYLIST,
+        "denylist",
+        vec![PARTICIPANT_ADDR.to_string()],
+        vec![],
+    )
+    .unwrap();
+
+    assert!(!is_allowed(deps.as_ref(), PARTICIPANT_ADDR.to_string()).unwrap());
+
+    // Denied holders can no longer move their tickets to someone else
+    let err = transfer_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        vec!["123456".to_string()],
+        ADMIN_ADDR.to_string(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::AddressNotAllowed);
+
+    set_winner_number(&mut deps, 1, "123456");
+
+    raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    let err = claim_prize(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::AddressNotAllowed);
+
+    update_address_list(
+        deps.as_mut(),
+        mock_info(ADMIN_ADDR, &[]),
+        DENYLIST,
+        "denylist",
+        vec![],
+        vec![PARTICIPANT_ADDR.to_string()],
+    )
+    .unwrap();
+
+    claim_prize(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        None,
+    )
+    .unwrap();
+
+    // Group members are looked up at purchase time
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            allowlist: Some(Allowlist::Group {
+                contract: Addr::unchecked("group"),
+            }),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    deps.querier.update_wasm(|query| match query {
+        WasmQuery::Smart { msg, .. } => {
+            let weight = match from_binary(msg).unwrap() {
+                Cw4QueryMsg::Member { addr, .. } if addr == "member" => Some(1),
+                _ => None,
+            };
+            SystemResult::Ok(ContractResult::Ok(
+                to_binary(&MemberResponse { weight }).unwrap(),
+            ))
+        }
+        _ => SystemResult::Err(SystemError::UnsupportedRequest {
+            kind: "wasm".to_string(),
+        }),
+    });
+
+    assert!(is_allowed(deps.as_ref(), "member".to_string()).unwrap());
+    assert!(!is_allowed(deps.as_ref(), PARTICIPANT_ADDR.to_string()).unwrap());
+}
+
+#[test]
+fn gaming_limits_should_hold_back_purchases() {
+    let mut deps = do_instantaite();
+
+    set_gaming_limits(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        Some(Uint128::from(TICKET_PRICE * 2)),
+        None,
+        Some(10),
+    )
+    .unwrap();
+
+    let buy = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, seconds: u64| {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(seconds);
+        buy_tickets(
+            deps.as_mut(),
+            env,
+            mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+            vec!["123456".to_string()],
+            1,
+            None,
+            None,
+        )
+    };
+
+    buy(&mut deps, 0).unwrap();
+    assert_eq!(
+        buy(&mut deps, 5).unwrap_err(),
+        ContractError::PurchaseCooldown
+    );
+    buy(&mut deps, 10).unwrap();
+    assert_eq!(
+        buy(&mut deps, 20).unwrap_err(),
+        ContractError::SpendingLimitExceeded
+    );
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(20);
+    let limits = get_gaming_limits(deps.as_ref(), env, PARTICIPANT_ADDR.to_string()).unwrap();
+    assert_eq!(limits.spent_last_day, Uint128::from(TICKET_PRICE * 2));
+    assert_eq!(limits.next_purchase_at, None);
+
+    let until = mock_env().block.time.plus_seconds(50);
+
+    self_exclude(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        until,
+    )
+    .unwrap();
+
+    // A self-exclusion cannot be cut short
+    let err = self_exclude(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        until.minus_seconds(1),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::InvalidExclusion);
+
+    // Lifting the limits only takes effect a day later
+    set_gaming_limits(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(buy(&mut deps, 30).unwrap_err(), ContractError::SelfExcluded);
+
+    let limits =
+        get_gaming_limits(deps.as_ref(), mock_env(), PARTICIPANT_ADDR.to_string()).unwrap();
+    assert_eq!(limits.limits.excluded_until, Some(until));
+    assert_eq!(limits.next_purchase_at, Some(until));
+    assert_eq!(
+        limits.limits.daily_limit,
+        Some(Uint128::from(TICKET_PRICE * 2))
+    );
+    assert_eq!(
+        limits.limits.pending.unwrap().activates_at,
+        mock_env().block.time.plus_seconds(86_400)
+    );
+
+    assert_eq!(
+        buy(&mut deps, 50).unwrap_err(),
+        ContractError::SpendingLimitExceeded
+    );
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(86_400);
+    let limits = get_gaming_limits(deps.as_ref(), env, PARTICIPANT_ADDR.to_string()).unwrap();
+    assert_eq!(limits.limits.daily_limit, None);
+    assert_eq!(limits.limits.cooldown, None);
+    assert_eq!(limits.limits.pending, None);
+}
+
+#[test]
+fn cooldown_longer_than_a_week_should_hold_back_purchases() {
+    let mut deps = do_instantaite();
+    let addr = Addr::unchecked(PARTICIPANT_ADDR);
+    let now = mock_env().block.time;
+
+    set_gaming_limits(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        None,
+        None,
+        Some(10 * 86_400),
+    )
+    .unwrap();
+
+    let amount = Uint128::from(TICKET_PRICE);
+    record_spend(deps.as_mut().storage, &addr, now, amount).unwrap();
+
+    assert_eq!(
+        record_spend(
+            deps.as_mut().storage,
+            &addr,
+            now.plus_seconds(8 * 86_400),
+            amount
+        )
+        .unwrap_err(),
+        ContractError::PurchaseCooldown
+    );
+    record_spend(
+        deps.as_mut().storage,
+        &addr,
+        now.plus_seconds(10 * 86_400),
+        amount,
+    )
+    .unwrap();
+}
+
+#[test]
+fn refunds_should_only_give_back_what_the_contract_still_holds() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            randomness_fee_bps: Some(1000),
+            min_participation: Some(MinParticipation {
+                tickets: 2,
+                policy: UnderSubscribedPolicy::Refund,
+            }),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    fund_pot(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("sponsor", &[coin(500, DENOM)]),
+        1,
+    )
+    .unwrap();
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    // The randomness fees were paid to Nois in the meantime
+    RANDOMNESS_FEES
+        .save(deps.as_mut().storage, DENOM, &Uint128::zero())
+        .unwrap();
+    deps.querier.update_balance(
+        MOCK_CONTRACT_ADDR,
+        vec![coin(500 + TICKET_PRICE - TICKET_PRICE / 10, DENOM)],
+    );
+
+    let mut expired = mock_env();
+    expired.block.time = expired.block.time.plus_seconds(60);
+    request_randomness(deps.as_mut(), expired, mock_info(PARTICIPANT_ADDR, &[]), 1).unwrap();
+
+    assert_eq!(
+        get_refund(deps.as_ref(), 1, PARTICIPANT_ADDR.to_string()).unwrap(),
+        coin(TICKET_PRICE, DENOM)
+    );
+    assert_eq!(
+        get_current_draw(deps.as_ref()).unwrap().total_prize.amount,
+        Uint128::from(500 - TICKET_PRICE / 10)
+    );
+
+    let solvency = get_solvency(deps.as_ref(), mock_env()).unwrap();
+    assert_eq!(solvency.deficit, Uint128::zero());
+    assert_eq!(solvency.surplus, Uint128::zero());
+}
+
+#[test]
+fn draw_supply_cap_and_participation_threshold_should_be_enforced() {
+    let buy = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, buyer: &str, draw_id| {
+        buy_tickets(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(buyer, &[coin(TICKET_PRICE, DENOM)]),
+            vec!["123456".to_string()],
+            draw_id,
+            None,
+            None,
+        )
+    };
+
+    let mut expired = mock_env();
+    expired.block.time = expired.block.time.plus_seconds(60);
+
+    // A sold out draw stops selling and can be closed before its end time
+    let mut deps = do_instantaite();
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            max_tickets_per_draw: Some(1),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    buy(&mut deps, PARTICIPANT_ADDR, 1).unwrap();
+    assert_eq!(
+        buy(&mut deps, "other", 1).unwrap_err(),
+        ContractError::DrawSoldOut
+    );
+    request_randomness(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap();
+    assert_eq!(
+        get_draw(deps.as_ref(), 1).unwrap().unwrap().status,
+        Status::Pending
+    );
+
+    let min_participation = |policy| UpdateConfigMsg {
+        min_participation: Some(MinParticipation { tickets: 2, policy }),
+        ..update_config_msg()
+    };
+
+    // Extending keeps the same draw open for another interval
+    let mut deps = do_instantaite();
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        min_participation(UnderSubscribedPolicy::Extend),
+    )
+    .unwrap();
+
+    buy(&mut deps, PARTICIPANT_ADDR, 1).unwrap();
+    let resp = request_randomness(
+        deps.as_mut(),
+        expired.clone(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap();
+    assert!(resp.messages.is_empty());
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.status, Status::Open);
+    assert_eq!(
+        draw.end_time,
+        Expiration::AtTime(expired.block.time.plus_seconds(60))
+    );
+
+    let buy_referred = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+        buy_tickets(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+            vec!["123456".to_string()],
+            1,
+            Some("referrer".to_string()),
+            None,
+        )
+    };
+
+    // Refunding cancels the draw and pays back whoever holds the tickets
+    let mut deps = do_instantaite();
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            referral_bps: Some(1000),
+            ..min_participation(UnderSubscribedPolicy::Refund)
+        },
+    )
+    .unwrap();
+
+    buy_referred(&mut deps).unwrap();
+    transfer_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+        vec!["123456".to_string()],
+        "holder".to_string(),
+    )
+    .unwrap();
+    request_randomness(
+        deps.as_mut(),
+        expired.clone(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_draw(deps.as_ref(), 1).unwrap().unwrap().status,
+        Status::Cancelled
+    );
+    assert_eq!(get_current_draw(deps.as_ref()).unwrap().id, 2);
+    assert_eq!(
+        get_refund(deps.as_ref(), 1, PARTICIPANT_ADDR.to_string()).unwrap(),
+        coin(0, DENOM)
+    );
+    assert_eq!(
+        get_refund(deps.as_ref(), 1, "holder".to_string()).unwrap(),
+        coin(TICKET_PRICE, DENOM)
+    );
+
+    // The referrer's credit goes away with the sale
+    let stats = get_referral_stats(deps.as_ref(), "referrer".to_string()).unwrap();
+    assert_eq!(stats.pending, Uint128::zero());
+    assert_eq!(stats.stats.total_earned, Uint128::zero());
+
+    let resp = claim_refund(deps.as_mut(), mock_env(), mock_info("holder", &[]), 1).unwrap();
+    assert_eq!(
+        resp.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "holder".to_string(),
+            amount: vec![coin(TICKET_PRICE, DENOM)],
+        })
+    );
+    assert_eq!(
+        claim_refund(deps.as_mut(), mock_env(), mock_info("holder", &[]), 1).unwrap_err(),
+        ContractError::NoRefund
+    );
+
+    // Rolling over carries the tickets, the pot and the referral credits into the next draw
+    let mut deps = do_instantaite();
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            referral_bps: Some(1000),
+            ..min_participation(UnderSubscribedPolicy::Rollover)
+        },
+    )
+    .unwrap();
+
+    buy_referred(&mut deps).unwrap();
+    let pot = get_draw(deps.as_ref(), 1).unwrap().unwrap().total_prize;
+    request_randomness(
+        deps.as_mut(),
+        expired.clone(),
+        mock_info(PARTICIPANT_ADDR, &[]),
+        1,
+    )
+    .unwrap();
+
+    // The cancelled draw can't be sent to Nois afterwards
+    let err = request_randomness(deps.as_mut(), expired, mock_info(PARTICIPANT_ADDR, &[]), 1)
+        .unwrap_err();
+    assert_eq!(err, ContractError::DrawIsNotOpen);
+
+    let next = get_current_draw(deps.as_ref()).unwrap();
+    assert_eq!(next.id, 2);
+    assert_eq!(next.total_prize, pot);
+    assert_eq!(next.total_tickets, 1);
+    assert_eq!(
+        get_tickets(deps.as_ref(), 2, PARTICIPANT_ADDR.to_string()).unwrap(),
+        vec!["123456".to_string()]
+    );
+    assert_eq!(next.referral_rewards, Uint128::from(TICKET_PRICE / 10));
+
+    let stats = get_referral_stats(deps.as_ref(), "referrer".to_string()).unwrap();
+    assert_eq!(stats.pending, Uint128::from(TICKET_PRICE / 10));
+    assert_eq!(stats.stats.total_earned, Uint128::from(TICKET_PRICE / 10));
+}
+
+#[test]
+fn anchored_schedule_should_not_drift() {
+    let mut deps = do_instantaite();
+    let anchor = Timestamp::from_seconds(1_571_797_400);
+
+    let err = update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            schedule_anchor: Some(Expiration::AtHeight(12_345)),
+            ..update_config_msg()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::InvalidSchedule);
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            schedule_anchor: Some(Expiration::AtTime(anchor)),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_schedule(deps.as_ref(), mock_env(), Some(3)).unwrap(),
+        vec![
+            Expiration::AtTime(anchor.plus_seconds(60)),
+            Expiration::AtTime(anchor.plus_seconds(120)),
+            Expiration::AtTime(anchor.plus_seconds(180)),
+        ]
+    );
+
+    // Raffled late, after a few missed slots, the next draw still ends on the schedule
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(200);
+    create_next_draw(deps.as_mut(), &env, Uint128::zero()).unwrap();
+
+    assert_eq!(
+        get_current_draw(deps.as_ref()).unwrap().end_time,
+        Expiration::AtTime(anchor.plus_seconds(240))
+    );
+
+    assert_eq!(next_slot(100, 10, 95).unwrap(), 100);
+    assert_eq!(next_slot(100, 10, 89).unwrap(), 90);
+    assert_eq!(next_slot(100, 10, 100).unwrap(), 110);
+    assert_eq!(next_slot(100, 10, 129).unwrap(), 130);
+}
+
+#[test]
+fn keeper_bounties_should_be_paid_once_per_stage() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            keeper_bounty: Some(KeeperBounty::Bps {
+                bps: 100,
+                cap: Some(Uint128::from(5u128)),
+            }),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let pot = get_draw(deps.as_ref(), 1).unwrap().unwrap().total_prize;
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(60);
+
+    let resp = request_randomness(deps.as_mut(), env.clone(), mock_info("keeper", &[]), 1).unwrap();
+    assert!(resp.messages.iter().any(|m| m.msg
+        == CosmosMsg::Bank(BankMsg::Send {
+            to_address: "keeper".to_string(),
+            amount: vec![coin(5, DENOM)],
+        })));
+
+    // Requesting again after a timeout pays nothing more
+    env.block.time = env.block.time.plus_seconds(60);
+    let resp = request_randomness(deps.as_mut(), env, mock_info("keeper", &[]), 1).unwrap();
+    assert!(!resp
+        .messages
+        .iter()
+        .any(|m| matches!(m.msg, CosmosMsg::Bank(_))));
+
+    set_winner_number(&mut deps, 1, "999999");
+
+    let resp = raffle(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("other_keeper", &[]),
+        1,
+        None,
+    )
+    .unwrap();
+    assert!(resp.messages.iter().any(|m| m.msg
+        == CosmosMsg::Bank(BankMsg::Send {
+            to_address: "other_keeper".to_string(),
+            amount: vec![coin(5, DENOM)],
+        })));
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.request_bounty, Some(coin(5, DENOM)));
+    assert_eq!(draw.raffle_bounty, Some(coin(5, DENOM)));
+    assert_eq!(draw.total_prize.amount, pot.amount - Uint128::from(10u128));
+}
+
+#[test]
+fn randomness_fees_should_be_paid_from_the_prepaid_balance() {
+    let mut deps = do_instantaite();
+    mock_nois_prices(&mut deps, vec![coin(50, "unois"), coin(150, DENOM)]);
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            randomness_fee_bps: Some(1000),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    buy_tickets(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(PARTICIPANT_ADDR, &[coin(TICKET_PRICE, DENOM)]),
+        vec!["123456".to_string()],
+        1,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let draw = get_draw(deps.as_ref(), 1).unwrap().unwrap();
+    assert_eq!(draw.randomness_fee_contribution, Uint128::from(100u128));
+    assert_eq!(
+        get_randomness_fees(deps.as_ref()).unwrap(),
+        vec![coin(100, DENOM)]
+    );
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(60);
+
+    let err =
+        request_randomness(deps.as_mut(), env.clone(), mock_info("keeper", &[]), 1).unwrap_err();
+    assert_eq!(err, ContractError::RandomnessFeesUnderfunded);
+
+    // Denoms the proxy doesn't take would be stuck in the balance
+    let err =
+        fund_randomness_fees(deps.as_mut(), mock_info("donor", &[coin(60, "uother")])).unwrap_err();
+    assert_eq!(err, ContractError::InvalidCoin);
+
+    fund_randomness_fees(deps.as_mut(), mock_info("donor", &[coin(60, DENOM)])).unwrap();
+
+    let resp = request_randomness(deps.as_mut(), env, mock_info("keeper", &[]), 1).unwrap();
+    assert!(resp.messages.iter().any(|m| matches!(
+        &m.msg,
+        CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, funds, .. })
+            if contract_addr == NOIS_ADDR && funds == &vec![coin(150, DENOM)]
+    )));
+    assert_eq!(
+        get_randomness_fees(deps.as_ref()).unwrap(),
+        vec![coin(10, DENOM)]
+    );
+    assert_eq!(
+        get_solvency(deps.as_ref(), mock_env())
+            .unwrap()
+            .randomness_fees,
+        Uint128::from(10u128)
+    );
+}
+
+#[test]
+fn small_draws_should_be_raffled_in_the_nois_callback() {
+    let mut deps = do_instantaite();
+
+    update_config(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ADDR, &[]),
+        UpdateConfigMsg {
+            auto_raffle_max_tickets: Some(1),
+            ..update_config_msg()
+        },
+    )
+    .unwrap();
+
+    let randomness = HexBinary::from(vec![
+        88, 85, 86, 91, 61, 64, 60, 71, 234, 24, 246, 200, 35, 73, 38, 187, 54, 59, 96, 9, 237, 27,
+        215, 103, 148, 230, 28, 48, 51, 114, 203, 219,
+    ]);
+
+    let mut env = mock_env();
+    for (draw_id, tickets) in [(1, vec!["123456"]), (2, vec!["123456", "654321"])] {
+        buy_tickets(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(
+                PARTICIPANT_ADDR,
+                &[coin(TICKET_PRICE * tickets.len() as u128, DENOM)],
+            ),
+            tickets.iter().map(|t| t.to_string()).collect(),
+            draw_id,
+            None,
+            None,
+        )
+        .unwrap();
+
+        env.block.time = env.block.time.plus_seconds(60);
+        request_randomness(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("keeper", &[]),
+            draw_id,
+        )
+        .unwrap();
+
+        let resp = receive_randomness(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(NOIS_ADDR, &[]),
+            NoisCallback {
+                job_id: draw_id.to_string(),
+                randomness: randomness.clone(),
+            },
+        )
+        .unwrap();
+
+        let raffled = resp.events.iter().any(|e| e.ty == "superstar.v1.MsgRaffle");
+        assert_eq!(raffled, draw_id == 1);
+    }
+
+    // The single ticket draw went straight to claimable and opened the next one
+    assert_eq!(
+        get_draw(deps.as_ref(), 1).unwrap().unwrap().status,
+        Status::Claimable
+    );
+    // The larger one waits for a manual raffle
+    assert_eq!(
+        get_draw(deps.as_ref(), 2).unwrap().unwrap().status,
+        Status::Raffling
+    );
+    raffle(
+        deps.as_mut(),
+        env,
+        mock_info(PARTICIPANT_ADDR, &[]),
+        2,
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        get_draw(deps.as_ref(), 2).unwrap().unwrap().status,
+        Status::Claimable
+    );
+}